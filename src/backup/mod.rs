@@ -0,0 +1,294 @@
+use crate::config::{self, Config, CONFIG_VERSION};
+use crate::crypto::{self, PASSPHRASE_ROUNDS};
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, Utc};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// Bumped whenever the layout of a backup changes.
+const FORMAT_VERSION: u32 = 1;
+const SALT_SIZE: usize = 16;
+/// Bounds on the PBKDF2 rounds a backup may ask for, so a damaged manifest
+/// cannot stall a restore.
+const MIN_ROUNDS: u32 = 100_000;
+const MAX_ROUNDS: u32 = 10_000_000;
+/// The SQLite database, copied through SQLite rather than file by file.
+#[cfg(feature = "sqlite")]
+const DATABASE: &str = "rnot.db";
+
+/// `manifest.json`, the first entry of every backup.
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    format: u32,
+    created_at: DateTime<Utc>,
+    rnot_version: String,
+    config_version: u32,
+    /// PBKDF2 salt (base64) and rounds of the key `secrets.enc` is
+    /// encrypted under.
+    salt: String,
+    rounds: u32,
+}
+
+/// The secrets as stored, encrypted, in `secrets.enc`.
+#[derive(Default, Serialize, Deserialize)]
+struct Secrets {
+    telegram_token: Option<String>,
+    #[serde(default)]
+    notifiers: BTreeMap<String, String>,
+}
+
+/// What a backup or restore covered.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub sites: usize,
+    pub secrets: usize,
+    /// Secrets that could not be decrypted with this machine's key, such as
+    /// "secret 'team'" or "Telegram token".
+    pub skipped: Vec<String>,
+}
+
+/// Writes config.toml, the whole data directory and every secret to `path`
+/// as a gzipped tar. The secrets are re-encrypted under `passphrase`, as
+/// the key they are stored under never leaves this machine.
+pub fn create(config: &mut Config, path: &Path, passphrase: &str) -> Result<Summary> {
+    let mut summary = Summary {
+        sites: config.app_config.sites.len(),
+        ..Default::default()
+    };
+
+    let mut secrets = Secrets {
+        telegram_token: config.get_telegram_token(),
+        ..Default::default()
+    };
+    if secrets.telegram_token.is_none() && config.stores_telegram_token() {
+        summary.skipped.push("Telegram token".to_string());
+    }
+    for name in config.secret_names()? {
+        match config.get_secret(&name) {
+            Some(value) => {
+                secrets.notifiers.insert(name, value);
+            }
+            None => summary.skipped.push(format!("secret '{}'", name)),
+        }
+    }
+    summary.secrets = secrets.notifiers.len() + secrets.telegram_token.iter().count();
+
+    let mut salt = [0u8; SALT_SIZE];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let key = crypto::derive_key(passphrase, &salt, PASSPHRASE_ROUNDS);
+
+    let manifest = Manifest {
+        format: FORMAT_VERSION,
+        created_at: Utc::now(),
+        rnot_version: env!("CARGO_PKG_VERSION").to_string(),
+        config_version: CONFIG_VERSION,
+        salt: BASE64.encode(salt),
+        rounds: PASSPHRASE_ROUNDS,
+    };
+    // Held throughout, so config.toml cannot change under the copy
+    let _lock = config::lock_config(&config.config_dir)?;
+    let config_toml = std::fs::read(config.config_dir.join("config.toml"))?;
+
+    // Written next to its destination and renamed, so a failed backup never
+    // replaces a good one; temp files are only readable by their owner
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let temp = tempfile::NamedTempFile::new_in(dir)?;
+    let mut builder = tar::Builder::new(GzEncoder::new(temp, Compression::default()));
+
+    append(
+        &mut builder,
+        "manifest.json",
+        &serde_json::to_vec_pretty(&manifest)?,
+    )?;
+    append(&mut builder, "config.toml", &config_toml)?;
+    append(
+        &mut builder,
+        "secrets.enc",
+        crypto::encrypt_with_key(&key, &serde_json::to_string(&secrets)?)?.as_bytes(),
+    )?;
+    append_dir(&mut builder, &config.data_dir, Path::new("data"))?;
+
+    let temp = builder.into_inner()?.finish()?;
+    temp.as_file().sync_all()?;
+    temp.persist(path)?;
+
+    Ok(summary)
+}
+
+fn append(builder: &mut tar::Builder<impl std::io::Write>, name: &str, data: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o600);
+    header.set_mtime(Utc::now().timestamp() as u64);
+    builder.append_data(&mut header, name, data)?;
+    Ok(())
+}
+
+fn append_dir(
+    builder: &mut tar::Builder<impl std::io::Write>,
+    dir: &Path,
+    name: &Path,
+) -> Result<()> {
+    let mut entries = std::fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let name = name.join(entry.file_name());
+        let file_type = entry.file_type()?;
+
+        // The daemon may be writing to the database, so it is copied
+        // through SQLite; what its journals hold is part of that copy
+        #[cfg(feature = "sqlite")]
+        if entry.file_name() == DATABASE {
+            append_database(builder, &path, &name)?;
+            continue;
+        } else if is_database_journal(&entry.file_name()) {
+            continue;
+        }
+
+        if file_type.is_dir() {
+            append_dir(builder, &path, &name)?;
+        } else if file_type.is_file() {
+            match builder.append_path_with_name(&path, &name) {
+                // Replaced or collected since we listed the directory
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                result => result?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+fn is_database_journal(file_name: &std::ffi::OsStr) -> bool {
+    ["-wal", "-shm", "-journal"]
+        .iter()
+        .any(|suffix| *file_name == *format!("{}{}", DATABASE, suffix))
+}
+
+#[cfg(feature = "sqlite")]
+fn append_database(
+    builder: &mut tar::Builder<impl std::io::Write>,
+    db: &Path,
+    name: &Path,
+) -> Result<()> {
+    let temp = tempfile::tempdir()?;
+    let copy = temp.path().join(DATABASE);
+    crate::storage::SqliteStorage::copy_database(db, &copy)?;
+    builder.append_path_with_name(&copy, name)?;
+    Ok(())
+}
+
+/// Restores a backup made by [`create`] into this machine's config and data
+/// directories, replacing what is there. Unless `force` is set, refuses to
+/// replace a config that already watches sites.
+pub fn restore(path: &Path, passphrase: &str, force: bool) -> Result<Summary> {
+    let config_dir = config::config_dir();
+    let data_dir = config::data_dir();
+    std::fs::create_dir_all(&config_dir)?;
+    std::fs::create_dir_all(&data_dir)?;
+
+    // Unpacked next to the data directory so it can be moved into place
+    let staging = tempfile::tempdir_in(data_dir.parent().unwrap_or(Path::new(".")))?;
+    let file = File::open(path).with_context(|| format!("Cannot read {}", path.display()))?;
+    tar::Archive::new(GzDecoder::new(file))
+        .unpack(staging.path())
+        .with_context(|| format!("{} is not an rnot backup", path.display()))?;
+
+    let manifest: Manifest = std::fs::read(staging.path().join("manifest.json"))
+        .ok()
+        .and_then(|manifest| serde_json::from_slice(&manifest).ok())
+        .with_context(|| format!("{} is not an rnot backup", path.display()))?;
+    if manifest.format > FORMAT_VERSION || manifest.config_version > CONFIG_VERSION {
+        anyhow::bail!(
+            "{} was made by rnot {}, which is newer than this one; please upgrade rnot",
+            path.display(),
+            manifest.rnot_version
+        );
+    }
+
+    if !(MIN_ROUNDS..=MAX_ROUNDS).contains(&manifest.rounds) {
+        anyhow::bail!(
+            "{} is damaged: its key takes {} rounds to derive",
+            path.display(),
+            manifest.rounds
+        );
+    }
+
+    // Check the passphrase before anything here is touched
+    let salt = BASE64.decode(&manifest.salt)?;
+    let key = crypto::derive_key(passphrase, &salt, manifest.rounds);
+    let encrypted = std::fs::read_to_string(staging.path().join("secrets.enc"))?;
+    let secrets: Secrets = serde_json::from_str(
+        &crypto::decrypt_with_key(&key, encrypted.trim())
+            .map_err(|_| anyhow::anyhow!("Wrong passphrase, or the backup is damaged"))?,
+    )?;
+    if !force {
+        if let Ok(existing) = std::fs::read_to_string(config_dir.join("config.toml")) {
+            match config::parse(&existing) {
+                Ok(existing) if existing.sites.is_empty() => {}
+                Ok(existing) => anyhow::bail!(
+                    "rnot already watches {} site(s) here; use --force to replace them",
+                    existing.sites.len()
+                ),
+                Err(_) => {
+                    anyhow::bail!("The config.toml here cannot be read; use --force to replace it")
+                }
+            }
+        }
+    }
+
+    // Upgrade and load the restored config where it was unpacked, so one
+    // that does not load leaves everything here as it was
+    let staged_data = staging.path().join("data");
+    std::fs::create_dir_all(&staged_data)?;
+    config::open(staging.path(), &staged_data)
+        .with_context(|| format!("The config in {} cannot be loaded", path.display()))?;
+    let config_toml = std::fs::read_to_string(staging.path().join("config.toml"))?;
+
+    // The old data directory goes with the staging directory once both
+    // renames and the config are through, and is put back otherwise
+    let old_data = staging.path().join("old-data");
+    std::fs::rename(&data_dir, &old_data)?;
+    let swapped = std::fs::rename(&staged_data, &data_dir)
+        .map_err(anyhow::Error::from)
+        .and_then(|()| {
+            config::replace_config(&config_dir, &config_toml).inspect_err(|_| {
+                let _ = std::fs::rename(&data_dir, &staged_data);
+            })
+        });
+    if let Err(e) = swapped {
+        std::fs::rename(&old_data, &data_dir)?;
+        return Err(e);
+    }
+
+    let mut config = Config::new()?;
+
+    for name in config.secret_names()? {
+        config.clear_secret(&name)?;
+    }
+    config.clear_telegram_token()?;
+    if let Some(token) = &secrets.telegram_token {
+        config.set_telegram_token(token)?;
+    }
+    for (name, value) in &secrets.notifiers {
+        config.set_secret(name, value)?;
+    }
+
+    Ok(Summary {
+        sites: config.app_config.sites.len(),
+        secrets: secrets.notifiers.len() + secrets.telegram_token.iter().count(),
+        skipped: Vec::new(),
+    })
+}
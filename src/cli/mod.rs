@@ -1,11 +1,16 @@
-use crate::config::Config;
+use crate::backup;
+use crate::config::{self, Config, WatchedSite};
 use crate::monitor::Monitor;
+use crate::notify::Notifiers;
 use crate::service::ServiceManager;
-use crate::storage::Storage;
-use crate::telegram::TelegramClient;
+use crate::storage;
+use crate::telegram::{Bot, ChatInfo, TelegramClient, TelegramMessage};
+use crate::transfer::{self, Format};
 use crate::tui::run_tui;
 use anyhow::Result;
 use clap::{Parser, Subcommand};
+use std::io::{self, Write};
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(name = "rnot")]
@@ -29,16 +34,116 @@ enum Commands {
         name: Option<String>,
         #[arg(short, long, help = "CSS selector to extract specific content")]
         selector: Option<String>,
+        #[arg(long, help = "Record the first number in the content on every check")]
+        track_value: bool,
+        #[arg(long, help = "Send alerts for this site even during quiet hours")]
+        urgent: bool,
+        #[arg(
+            long,
+            help = "Keep the raw response of every change for `rnot reprocess`"
+        )]
+        archive_raw: bool,
+        #[arg(
+            long = "notify",
+            help = "Notifier to route this site to (repeatable, default: all)"
+        )]
+        notify: Vec<String>,
+        #[arg(
+            long = "tag",
+            help = "Tag the site, putting it in that group (repeatable)"
+        )]
+        tags: Vec<String>,
     },
 
-    #[command(about = "Remove a site from watching")]
+    #[command(about = "Remove a site, or every site with a tag, from watching")]
     Remove {
-        #[arg(help = "ID or URL of the site to remove")]
-        site: String,
+        #[arg(
+            help = "ID or URL of the site to remove",
+            required_unless_present = "tag"
+        )]
+        site: Option<String>,
+        #[arg(
+            long,
+            conflicts_with = "site",
+            help = "Remove every site with this tag"
+        )]
+        tag: Option<String>,
     },
 
     #[command(about = "List all watched sites")]
-    List,
+    List {
+        #[arg(long, help = "Only list sites with this tag")]
+        tag: Option<String>,
+    },
+
+    #[command(about = "Pause checks for a site, or every site with a tag")]
+    Pause {
+        #[arg(help = "ID or URL of the site", required_unless_present = "tag")]
+        site: Option<String>,
+        #[arg(long, conflicts_with = "site", help = "Pause every site with this tag")]
+        tag: Option<String>,
+    },
+
+    #[command(about = "Resume checks for a site, or every site with a tag")]
+    Resume {
+        #[arg(help = "ID or URL of the site", required_unless_present = "tag")]
+        site: Option<String>,
+        #[arg(
+            long,
+            conflicts_with = "site",
+            help = "Resume every site with this tag"
+        )]
+        tag: Option<String>,
+    },
+
+    #[command(about = "Export watched sites to a file or stdout")]
+    Export {
+        #[arg(
+            short,
+            long,
+            value_enum,
+            help = "Format to write (default: from the output file extension, else toml)"
+        )]
+        format: Option<Format>,
+        #[arg(short, long, help = "File to write instead of stdout")]
+        output: Option<PathBuf>,
+        #[arg(
+            long,
+            help = "Include runtime state such as the last check (json and toml only)"
+        )]
+        with_state: bool,
+    },
+
+    #[command(about = "Import sites from an OPML, JSON, CSV or TOML file")]
+    Import {
+        #[arg(help = "File to import")]
+        file: PathBuf,
+        #[arg(
+            short,
+            long,
+            value_enum,
+            help = "Format to read (default: from the file extension)"
+        )]
+        format: Option<Format>,
+        #[arg(long, help = "Show what would be added without changing anything")]
+        dry_run: bool,
+        #[arg(long, help = "Also import runtime state found in the file")]
+        with_state: bool,
+    },
+
+    #[command(about = "Back up config.toml, history and secrets to a single file")]
+    Backup {
+        #[arg(help = "File to write")]
+        file: PathBuf,
+    },
+
+    #[command(about = "Restore a backup made with `rnot backup`, e.g. on another machine")]
+    Restore {
+        #[arg(help = "Backup file to restore")]
+        file: PathBuf,
+        #[arg(long, help = "Replace the sites already watched here")]
+        force: bool,
+    },
 
     #[command(about = "Setup Telegram token (will be encrypted)")]
     SetToken {
@@ -49,18 +154,62 @@ enum Commands {
     #[command(about = "Clear stored Telegram token")]
     ClearToken,
 
+    #[command(about = "Store a notifier secret (will be encrypted)")]
+    SetSecret {
+        #[arg(help = "Notifier name the secret belongs to")]
+        name: String,
+        #[arg(help = "Secret value (password, signing key or access token)")]
+        value: String,
+    },
+
+    #[command(about = "Clear a stored notifier secret")]
+    ClearSecret {
+        #[arg(help = "Notifier name the secret belongs to")]
+        name: String,
+    },
+
     #[command(about = "Setup Telegram chat ID")]
-    TelegramSetup,
+    TelegramSetup {
+        #[arg(
+            long,
+            help = "Add the chat as a named destination instead of the default chat"
+        )]
+        name: Option<String>,
+    },
 
     #[command(about = "Run the monitor daemon (background service)")]
     Daemon,
 
     #[command(about = "Check all sites once")]
-    Check,
+    Check {
+        #[arg(long, help = "Only check sites with this tag")]
+        tag: Option<String>,
+    },
 
     #[command(about = "Show current configuration status")]
     Status,
 
+    #[command(about = "Inspect config.toml")]
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+
+    #[command(about = "Trim history to the retention limits and delete unreferenced snapshots")]
+    Gc,
+
+    #[command(about = "Manage the storage backend")]
+    Storage {
+        #[command(subcommand)]
+        command: StorageCommand,
+    },
+
+    #[command(about = "Rebuild a site's history from its archived responses")]
+    Reprocess {
+        #[arg(help = "ID or URL of the site")]
+        site: String,
+    },
+
     #[command(about = "Install system service (run on boot)")]
     InstallService {
         #[arg(long, help = "Skip confirmation prompts")]
@@ -74,10 +223,91 @@ enum Commands {
     ServiceStatus,
 }
 
-pub async fn run(mut config: Config) -> Result<()> {
+#[derive(Subcommand)]
+enum ConfigCommand {
+    #[command(about = "Report every problem in config.toml; exits non-zero if there are any")]
+    Validate {
+        #[arg(help = "File to check instead of the active config.toml")]
+        file: Option<PathBuf>,
+        #[arg(
+            long,
+            help = "Fail on warnings too, such as unknown keys; use this in CI"
+        )]
+        strict: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum StorageCommand {
+    #[command(about = "Move history and snapshots to the backend selected in config.toml")]
+    Migrate,
+}
+
+pub async fn run() -> Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
+        // Has to work on files that Config::new refuses to load
+        Commands::Config { command } => config_command(command),
+        // Replaces whatever Config::new would load
+        Commands::Restore { file, force } => {
+            let passphrase = backup_passphrase(false)?;
+            let summary = backup::restore(&file, &passphrase, force)?;
+            println!(
+                "Restored {} site(s) and {} secret(s) from {}",
+                summary.sites,
+                summary.secrets,
+                file.display()
+            );
+            Ok(())
+        }
+        command => run_command(command, Config::new()?).await,
+    }
+}
+
+fn config_command(command: ConfigCommand) -> Result<()> {
+    match command {
+        ConfigCommand::Validate { file, strict } => {
+            let file = file.unwrap_or_else(|| config::config_dir().join("config.toml"));
+            let content = std::fs::read_to_string(&file)
+                .map_err(|e| anyhow::anyhow!("Cannot read {}: {}", file.display(), e))?;
+            let report = config::validate(&content);
+
+            for error in &report.errors {
+                println!("error: {}", error);
+            }
+            for warning in &report.warnings {
+                println!("warning: {}", warning);
+            }
+
+            if !report.errors.is_empty() || (strict && !report.warnings.is_empty()) {
+                anyhow::bail!(
+                    "{} has {} error(s) and {} warning(s)",
+                    file.display(),
+                    report.errors.len(),
+                    report.warnings.len()
+                );
+            }
+            if report.warnings.is_empty() {
+                println!("{} is valid", file.display());
+            } else {
+                println!(
+                    "{} is valid, with {} warning(s); --strict fails on them",
+                    file.display(),
+                    report.warnings.len()
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_command(command: Commands, mut config: Config) -> Result<()> {
+    match command {
+        Commands::Config { .. } | Commands::Restore { .. } => {
+            unreachable!("handled before loading the config")
+        }
         Commands::Tui => {
             let token = config.get_telegram_token();
             if token.is_none() {
@@ -88,9 +318,9 @@ pub async fn run(mut config: Config) -> Result<()> {
 
             let (events_tx, events_rx) = tokio::sync::mpsc::channel(100);
 
-            let telegram = TelegramClient::new(token, config.app_config.telegram_chat_id.clone());
-            let storage = Storage::new(config.data_dir.clone(), config.config_dir.clone());
-            let monitor = Monitor::new(telegram, storage);
+            let notifiers = Notifiers::from_config(&mut config, Some(events_tx.clone()));
+            let storage = storage::open(&config)?;
+            let monitor = Monitor::new(notifiers, storage);
 
             let (shutdown_tx, _) = tokio::sync::broadcast::channel(1);
             let shutdown_tx_clone = shutdown_tx.clone();
@@ -110,7 +340,16 @@ pub async fn run(mut config: Config) -> Result<()> {
             url,
             name,
             selector,
+            track_value,
+            urgent,
+            archive_raw,
+            notify,
+            tags,
         } => {
+            for tag in &tags {
+                config::validate_tag(tag)?;
+            }
+
             let site_name = name.unwrap_or_else(|| {
                 url::Url::parse(&url)
                     .ok()
@@ -119,22 +358,48 @@ pub async fn run(mut config: Config) -> Result<()> {
             });
 
             let id = config.add_site(url, site_name.clone(), selector)?;
+            if track_value || urgent || archive_raw || !notify.is_empty() || !tags.is_empty() {
+                config.modify(|app_config| {
+                    if let Some(site) = app_config.sites.iter_mut().find(|s| s.id == id) {
+                        site.track_value = track_value;
+                        site.urgent = urgent;
+                        site.archive_raw = archive_raw;
+                        if !notify.is_empty() {
+                            site.notifiers = Some(notify);
+                        }
+                        site.tags = tags;
+                    }
+                    Ok(())
+                })?;
+            }
             println!("Added site '{}' with ID: {}", site_name, id);
         }
-        Commands::Remove { site } => {
-            if config.remove_site(&site)? {
-                println!("Site removed successfully");
-            } else {
-                println!("Site not found");
+        Commands::Remove { site, tag } => {
+            let removed = config.remove_sites(site_filter(site.as_deref(), tag.as_deref()))?;
+            match tag {
+                _ if removed.is_empty() => println!("{}", not_found(tag.as_deref())),
+                Some(tag) => println!("Removed {} site(s) tagged '{}'", removed.len(), tag),
+                None => println!("Site removed successfully"),
             }
         }
-        Commands::List => {
-            if config.app_config.sites.is_empty() {
-                println!("No sites being watched");
+        Commands::List { tag } => {
+            let selected = site_filter(None, tag.as_deref());
+            let sites: Vec<&WatchedSite> = config
+                .app_config
+                .sites
+                .iter()
+                .filter(|s| selected(s))
+                .collect();
+
+            if sites.is_empty() {
+                match tag {
+                    Some(_) => println!("{}", not_found(tag.as_deref())),
+                    None => println!("No sites being watched"),
+                }
             } else {
                 println!("Watched Sites:");
                 println!("{:-<60}", "");
-                for site in &config.app_config.sites {
+                for site in sites {
                     let status = if site.enabled { "✓" } else { "✗" };
                     let last = site
                         .last_checked
@@ -146,10 +411,139 @@ pub async fn run(mut config: Config) -> Result<()> {
                     if let Some(ref sel) = site.css_selector {
                         println!("  Selector: {}", sel);
                     }
+                    if !site.tags.is_empty() {
+                        println!("  Tags: {}", site.tags.join(", "));
+                    }
+                    if let Some(ref names) = site.notifiers {
+                        println!("  Notifiers: {}", names.join(", "));
+                    } else if let Some(ref names) = site.inherited.notifiers {
+                        println!("  Notifiers: {} (from its group)", names.join(", "));
+                    }
+                    let interval = site.interval_secs(config.app_config.check_interval_secs);
+                    if interval != config.app_config.check_interval_secs {
+                        println!("  Check interval: {} seconds", interval);
+                    }
+                    if let Some(change) = site.last_change {
+                        let seen = if site.has_unseen_change() {
+                            " (unseen)"
+                        } else {
+                            ""
+                        };
+                        println!(
+                            "  Last change: {}{}",
+                            change.format("%Y-%m-%d %H:%M:%S"),
+                            seen
+                        );
+                    }
+                    if site.urgent {
+                        println!("  Urgent");
+                    }
+                    if site.archive_raw {
+                        println!("  Archiving raw responses");
+                    }
+                    if site.muted {
+                        println!("  Muted");
+                    } else if let Some(until) = site.snoozed_until.filter(|_| site.is_silenced()) {
+                        println!("  Snoozed until: {}", until.format("%Y-%m-%d %H:%M:%S"));
+                    }
                     println!();
                 }
             }
         }
+        Commands::Export {
+            format,
+            output,
+            with_state,
+        } => {
+            let format = format
+                .or_else(|| output.as_deref().and_then(Format::from_path))
+                .unwrap_or(Format::Toml);
+            let exported = transfer::export(&config.app_config.sites, format, with_state)?;
+
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, exported)?;
+                    println!(
+                        "Exported {} site(s) to {}",
+                        config.app_config.sites.len(),
+                        path.display()
+                    );
+                }
+                None => print!("{}", exported),
+            }
+        }
+        Commands::Import {
+            file,
+            format,
+            dry_run,
+            with_state,
+        } => {
+            let Some(format) = format.or_else(|| Format::from_path(&file)) else {
+                anyhow::bail!("Cannot tell the format of {}; use --format", file.display());
+            };
+            let content = std::fs::read_to_string(&file)?;
+            let entries = transfer::parse(&content, format)
+                .map_err(|e| anyhow::anyhow!("Failed to read {}: {:#}", file.display(), e))?;
+            let plan = transfer::plan(&config.app_config.sites, entries);
+
+            let verb = if dry_run { "Would add" } else { "Adding" };
+            for entry in &plan.added {
+                println!("{}: {} ({})", verb, entry.site.name, entry.site.url);
+            }
+            for entry in &plan.duplicates {
+                println!("Already watched: {} ({})", entry.site.name, entry.site.url);
+            }
+            for (entry, reason) in &plan.invalid {
+                println!(
+                    "Skipping {} ({}): {}",
+                    entry.site.name, entry.site.url, reason
+                );
+            }
+
+            if !dry_run && !plan.added.is_empty() {
+                config.modify(|app_config| {
+                    for entry in &plan.added {
+                        // Unless another process added it in the meantime
+                        if !app_config.sites.iter().any(|s| s.id == entry.site.id) {
+                            app_config.sites.push(entry.site.clone());
+                        }
+                    }
+                    Ok(())
+                })?;
+
+                if with_state {
+                    for entry in &plan.added {
+                        let mut site = entry.site.clone();
+                        entry.state.clone().apply(&mut site);
+                        config.save_state(&site)?;
+                        config.save_alert_state(&site)?;
+                    }
+                }
+            }
+
+            println!(
+                "{} site(s) {}, {} already watched, {} invalid",
+                plan.added.len(),
+                if dry_run { "to add" } else { "added" },
+                plan.duplicates.len(),
+                plan.invalid.len()
+            );
+        }
+        Commands::Backup { file } => {
+            let passphrase = backup_passphrase(true)?;
+            let summary = backup::create(&mut config, &file, &passphrase)?;
+            for name in &summary.skipped {
+                println!("Warning: the {} cannot be decrypted and was left out", name);
+            }
+            println!(
+                "Backed up {} site(s) and {} secret(s) to {}",
+                summary.sites,
+                summary.secrets,
+                file.display()
+            );
+        }
+        Commands::Pause { site, tag } => set_enabled(&mut config, site, tag, false)?,
+        Commands::Resume { site, tag } => set_enabled(&mut config, site, tag, true)?,
         Commands::SetToken { token } => {
             config.set_telegram_token(&token)?;
             println!("Telegram token stored securely (encrypted)");
@@ -158,66 +552,92 @@ pub async fn run(mut config: Config) -> Result<()> {
             config.clear_telegram_token()?;
             println!("Telegram token cleared");
         }
-        Commands::TelegramSetup => {
-            let token = config.get_telegram_token().ok_or_else(|| {
-                anyhow::anyhow!("No Telegram token set. Use 'rnot set-token <TOKEN>' first.")
-            })?;
-
-            let telegram = TelegramClient::new(Some(token), None);
-
-            println!("Send any message to your bot on Telegram...");
-
-            for _ in 0..30 {
-                if let Ok(Some(chat_id)) = telegram.get_chat_id_from_updates().await {
-                    config.set_telegram_chat_id(chat_id.clone())?;
-                    println!("Chat ID set to: {}", chat_id);
-                    return Ok(());
-                }
-                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        Commands::SetSecret { name, value } => {
+            config.set_secret(&name, &value)?;
+            println!("Secret for '{}' stored securely (encrypted)", name);
+        }
+        Commands::ClearSecret { name } => {
+            if config.clear_secret(&name)? {
+                println!("Secret for '{}' cleared", name);
+            } else {
+                println!("No secret stored for '{}'", name);
             }
-
-            println!(
-                "Timeout: No messages received. Please send a message to your bot and try again."
-            );
         }
+        Commands::TelegramSetup { name } => telegram_setup(&mut config, name).await?,
         Commands::Daemon => {
-            let token = config.get_telegram_token();
+            let notifiers = Notifiers::from_config(&mut config, None);
 
-            if token.is_none() {
-                println!("Warning: No Telegram token set. Notifications will be disabled.");
-                println!("Use 'rnot set-token <TOKEN>' to enable notifications.");
+            if notifiers.is_empty() {
+                println!("Warning: No notifiers configured. Notifications will be disabled.");
+                println!(
+                    "Use 'rnot set-token <TOKEN>' and 'rnot telegram-setup' to enable Telegram."
+                );
+            } else {
+                println!("Notifiers: {}", notifiers.names().join(", "));
             }
 
             println!("Starting daemon mode...");
 
-            let telegram = TelegramClient::new(token, config.app_config.telegram_chat_id.clone());
-            let storage = Storage::new(config.data_dir.clone(), config.config_dir.clone());
-            let monitor = Monitor::new(telegram, storage);
+            let storage = storage::open(&config)?;
+            let monitor = Monitor::new(notifiers, storage);
 
             let (events_tx, _) = tokio::sync::mpsc::channel(100);
             let (_sites_tx, sites_rx) = tokio::sync::mpsc::channel(1);
-            let (_, shutdown_rx) = tokio::sync::broadcast::channel(1);
+            let (shutdown_tx, shutdown_rx) = tokio::sync::broadcast::channel(1);
 
-            monitor.run(sites_rx, events_tx, shutdown_rx).await;
-        }
-        Commands::Check => {
             let token = config.get_telegram_token();
-            let telegram = TelegramClient::new(token, config.app_config.telegram_chat_id.clone());
-            let storage = Storage::new(config.data_dir.clone(), config.config_dir.clone());
-            let monitor = Monitor::new(telegram, storage);
+            let chats = config.app_config.telegram_chats();
+            match token {
+                Some(token) if !chats.is_empty() => {
+                    println!(
+                        "Telegram bot commands enabled for chat(s) {}",
+                        chats.join(", ")
+                    );
+                    let storage = storage::open(&config)?;
+                    let bot = Bot::new(token, chats, storage);
+
+                    tokio::join!(
+                        monitor.run(sites_rx, events_tx, shutdown_rx),
+                        bot.run(&mut config, &monitor, shutdown_tx.subscribe()),
+                    );
+                }
+                _ => monitor.run(sites_rx, events_tx, shutdown_rx).await,
+            }
+        }
+        Commands::Check { tag } => {
+            let storage = storage::open(&config)?;
+            let monitor = Monitor::new(Notifiers::new(Vec::new()), storage);
+
+            let selected = site_filter(None, tag.as_deref());
+            let sites: Vec<WatchedSite> = config
+                .app_config
+                .sites
+                .iter()
+                .filter(|s| selected(s))
+                .cloned()
+                .collect();
+            if sites.is_empty() && tag.is_some() {
+                println!("{}", not_found(tag.as_deref()));
+            }
 
-            for mut site in config.app_config.sites.clone() {
+            for mut site in sites {
                 match monitor.check_site(&mut site).await {
-                    Ok(Some(diff)) => {
+                    Ok(Some(change)) => {
                         println!(
                             "Changed: {} - {}",
                             site.name,
-                            diff.chars().take(100).collect::<String>()
+                            change
+                                .diff
+                                .to_string()
+                                .chars()
+                                .take(100)
+                                .collect::<String>()
                         );
-                        config.update_site(&site)?;
+                        config.save_state(&site)?;
                     }
                     Ok(None) => {
                         println!("No change: {}", site.name);
+                        config.save_state(&site)?;
                     }
                     Err(e) => {
                         println!("Error checking {}: {}", site.name, e);
@@ -247,14 +667,85 @@ pub async fn run(mut config: Config) -> Result<()> {
                 .unwrap_or("✗ Not set");
             println!("Telegram Chat ID: {}", chat_id);
 
+            let notifiers: Vec<&str> = config
+                .app_config
+                .notifiers
+                .iter()
+                .map(|n| n.name())
+                .collect();
+            if !notifiers.is_empty() {
+                println!("Notifiers: {}", notifiers.join(", "));
+            }
+
             println!(
                 "Check Interval: {} seconds",
                 config.app_config.check_interval_secs
             );
             println!("Watched Sites: {}", config.app_config.sites.len());
+            if !config.app_config.groups.is_empty() {
+                let groups: Vec<&str> = config
+                    .app_config
+                    .groups
+                    .keys()
+                    .map(String::as_str)
+                    .collect();
+                println!("Groups: {}", groups.join(", "));
+            }
             println!("Config Dir: {}", config.config_dir.display());
             println!("Data Dir: {}", config.data_dir.display());
         }
+        Commands::Gc => {
+            let storage = storage::open(&config)?;
+            let report = storage.gc(&config.app_config.sites, &config.app_config.retention)?;
+            println!(
+                "Removed {} check(s) and {} archived response(s) past the retention limits",
+                report.checks_removed, report.responses_removed
+            );
+            println!(
+                "Removed {} unreferenced snapshot(s) and the archives of {} removed site(s), \
+                 freed {} KB",
+                report.removed,
+                report.archives_removed,
+                report.freed_bytes.div_ceil(1024)
+            );
+        }
+        Commands::Storage {
+            command: StorageCommand::Migrate,
+        } => {
+            let selected = config.app_config.storage.name();
+            match storage::migrate(&config)? {
+                Some(report) => println!(
+                    "Moved {} site(s) with {} check(s), {} archived response(s) and {} queued alert(s) from {} to {}; the {} data was left in place",
+                    report.sites,
+                    report.checks,
+                    report.responses,
+                    report.alerts,
+                    report.from.name(),
+                    selected,
+                    report.from.name()
+                ),
+                None => println!("The data is already in {} storage", selected),
+            }
+        }
+        Commands::Reprocess { site } => {
+            let Some(mut site) = config
+                .app_config
+                .sites
+                .iter()
+                .find(|s| s.id == site || s.url == site)
+                .cloned()
+            else {
+                anyhow::bail!("Site not found: {}", site);
+            };
+
+            let monitor = Monitor::new(Notifiers::new(Vec::new()), storage::open(&config)?);
+            let versions = monitor.reprocess(&mut site)?;
+            config.save_state(&site)?;
+            println!(
+                "Rebuilt {} version(s) of {} from its archived responses",
+                versions, site.name
+            );
+        }
         Commands::InstallService { yes } => {
             ServiceManager::install(yes)?;
         }
@@ -268,3 +759,182 @@ pub async fn run(mut config: Config) -> Result<()> {
 
     Ok(())
 }
+
+/// Selects the site with the ID or URL `site` and/or the sites tagged
+/// `tag`; all sites if neither is given.
+fn site_filter<'a>(
+    site: Option<&'a str>,
+    tag: Option<&'a str>,
+) -> impl Fn(&WatchedSite) -> bool + 'a {
+    move |s| {
+        site.is_none_or(|site| s.id == site || s.url == site)
+            && tag.is_none_or(|tag| s.has_tag(tag))
+    }
+}
+
+fn not_found(tag: Option<&str>) -> String {
+    match tag {
+        Some(tag) => format!("No sites tagged '{}'", tag),
+        None => "Site not found".to_string(),
+    }
+}
+
+/// Pauses or resumes checks for the selected sites.
+fn set_enabled(
+    config: &mut Config,
+    site: Option<String>,
+    tag: Option<String>,
+    enabled: bool,
+) -> Result<()> {
+    let selected = site_filter(site.as_deref(), tag.as_deref());
+    let names: Vec<String> = config.modify(|app_config| {
+        Ok(app_config
+            .sites
+            .iter_mut()
+            .filter(|s| selected(s))
+            .map(|s| {
+                s.enabled = enabled;
+                s.name.clone()
+            })
+            .collect())
+    })?;
+
+    if names.is_empty() {
+        println!("{}", not_found(tag.as_deref()));
+    }
+    let action = if enabled { "Resumed" } else { "Paused" };
+    for name in names {
+        println!("{} {}", action, name);
+    }
+    Ok(())
+}
+
+/// The passphrase protecting the secrets in a backup, from
+/// `RNOT_BACKUP_PASSPHRASE` or typed in; new ones are asked for twice.
+fn backup_passphrase(confirm: bool) -> Result<String> {
+    let passphrase = match std::env::var("RNOT_BACKUP_PASSPHRASE") {
+        Ok(passphrase) => passphrase,
+        Err(_) => {
+            let prompt = |text| {
+                rpassword::prompt_password(text).map_err(|e| {
+                    anyhow::anyhow!(
+                        "Cannot ask for the passphrase ({}); set RNOT_BACKUP_PASSPHRASE",
+                        e
+                    )
+                })
+            };
+            let passphrase = prompt("Backup passphrase: ")?;
+            if confirm && prompt("Repeat passphrase: ")? != passphrase {
+                anyhow::bail!("The passphrases do not match");
+            }
+            passphrase
+        }
+    };
+
+    if passphrase.is_empty() {
+        anyhow::bail!("The passphrase cannot be empty");
+    }
+    Ok(passphrase)
+}
+
+async fn telegram_setup(config: &mut Config, name: Option<String>) -> Result<()> {
+    let token = config.get_telegram_token().ok_or_else(|| {
+        anyhow::anyhow!("No Telegram token set. Use 'rnot set-token <TOKEN>' first.")
+    })?;
+
+    let telegram = TelegramClient::new(Some(token.clone()), None);
+    let username = telegram.get_me().await?;
+    println!("Connected to bot @{}", username);
+    println!();
+    println!("Send /start to @{} from the chat to use:", username);
+    println!("  - a private chat: just message the bot");
+    println!("  - a group or forum topic: add the bot, then send /start there");
+    println!("  - a channel: add the bot as an admin, then post in the channel");
+    println!();
+    println!("Waiting for messages...");
+
+    let mut updates = Vec::new();
+    let mut offset = None;
+    for _ in 0..6 {
+        let batch = telegram.poll_updates(offset, 10).await?;
+        if let Some(last) = batch.last().and_then(|u| u["update_id"].as_i64()) {
+            offset = Some(last + 1);
+        }
+        updates.extend(batch);
+
+        if !ChatInfo::from_updates(&updates).is_empty() {
+            break;
+        }
+    }
+
+    let chats = ChatInfo::from_updates(&updates);
+    if chats.is_empty() {
+        println!("Timeout: No messages received. Please send a message to your bot and try again.");
+        return Ok(());
+    }
+
+    println!();
+    println!("Chats that messaged the bot:");
+    for (i, chat) in chats.iter().enumerate() {
+        let topic = chat
+            .thread_id
+            .map(|id| format!(", topic {}", id))
+            .unwrap_or_default();
+        println!(
+            "  {}) {} ({}{}) [{}]",
+            i + 1,
+            chat.title,
+            chat.kind,
+            topic,
+            chat.chat_id
+        );
+    }
+    println!();
+    print!("Select [1-{}] (default: 1): ", chats.len());
+    io::stdout().flush()?;
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    let chat = match input.trim() {
+        "" => &chats[0],
+        choice => choice
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| n.checked_sub(1))
+            .and_then(|i| chats.get(i))
+            .ok_or_else(|| anyhow::anyhow!("Invalid choice: {}", choice))?,
+    };
+
+    // Confirm the updates we read so they are not delivered again
+    if let Some(offset) = offset {
+        telegram.poll_updates(Some(offset), 0).await?;
+    }
+
+    let destination =
+        TelegramClient::new(Some(token), Some(chat.chat_id.clone())).with_thread(chat.thread_id);
+    let test = format!("✅ RNOT will send notifications to {} here.", chat.title);
+    for message in TelegramMessage::text_parts(&test) {
+        destination
+            .send_message(&message)
+            .await
+            .map_err(|e| anyhow::anyhow!("Test message to {} failed: {}", chat.title, e))?;
+    }
+
+    match name {
+        Some(ref name) => {
+            config.set_telegram_destination(name, chat.chat_id.clone(), chat.thread_id)?;
+            println!("Destination '{}' set to {}", name, chat.title);
+            println!("Route sites to it with 'rnot add <URL> --notify {}'", name);
+        }
+        None => {
+            if chat.thread_id.is_some() {
+                println!("Note: the default chat has no topic; use --name to post in a topic");
+            }
+            config.set_telegram_chat_id(chat.chat_id.clone())?;
+            println!("Chat ID set to: {} ({})", chat.chat_id, chat.title);
+        }
+    }
+    println!("Test message sent.");
+
+    Ok(())
+}
@@ -0,0 +1,219 @@
+use super::{lock_config, write_config, CONFIG_VERSION};
+use crate::state::{SiteState, StateStore};
+use anyhow::{Context, Result};
+use std::path::Path;
+use toml::Table;
+
+/// A step upgrading a config from the version at its index to the next.
+type Step = fn(&mut Table, &Path) -> Result<()>;
+
+/// One step per version bump, in order. `STEPS.len()` must equal
+/// [`CONFIG_VERSION`].
+const STEPS: [Step; 1] = [move_runtime_state];
+
+/// Runtime state that configs before version 1 kept per site. Released
+/// versions only wrote the first three; the rest covers configs from
+/// pre-release builds of this series, which kept alert state there too.
+const RUNTIME_KEYS: [&str; 7] = [
+    "last_hash",
+    "last_checked",
+    "last_change",
+    "last_error",
+    "muted",
+    "snoozed_until",
+    "last_seen",
+];
+
+/// Upgrades config.toml in `config_dir` to [`CONFIG_VERSION`], keeping the
+/// original as `config.toml.v<N>.bak`. Does nothing if it is current.
+pub fn run(config_dir: &Path, data_dir: &Path) -> Result<()> {
+    let lock = lock_config(config_dir)?;
+
+    let config_file = config_dir.join("config.toml");
+    let content = std::fs::read_to_string(&config_file)?;
+    let mut config: Table = content.parse().map_err(|e: toml::de::Error| {
+        super::describe_error(&content, e.span(), None, e.message())
+    })?;
+
+    let version = version_of(&config)?;
+    if version > CONFIG_VERSION {
+        anyhow::bail!(
+            "config.toml is version {} but this rnot only understands up to version {}; \
+             please upgrade rnot",
+            version,
+            CONFIG_VERSION
+        );
+    }
+    if version == CONFIG_VERSION {
+        return Ok(());
+    }
+
+    let backup = config_dir.join(format!("config.toml.v{}.bak", version));
+    if !backup.exists() {
+        std::fs::copy(&config_file, &backup)?;
+    }
+
+    upgrade(&mut config, version, data_dir)?;
+
+    // Make sure the result loads before replacing the original
+    let migrated = toml::to_string_pretty(&config)?;
+    if let Err(e) = super::parse(&migrated) {
+        // Line numbers in the original are the ones users can look up
+        let e = super::parse(&content).err().unwrap_or(e);
+        return Err(e.context(format!(
+            "config.toml could not be migrated to version {} and was left unchanged",
+            CONFIG_VERSION
+        )));
+    }
+    write_config(config_dir, &migrated)?;
+    drop(lock);
+
+    tracing::info!(
+        "Migrated config.toml from version {} to {} (backup: {})",
+        version,
+        CONFIG_VERSION,
+        backup.display()
+    );
+    Ok(())
+}
+
+/// The schema version of a parsed config.toml; files written before
+/// versioning are 0.
+pub fn version_of(config: &Table) -> Result<u32> {
+    match config.get("version") {
+        None => Ok(0),
+        Some(value) => value
+            .as_integer()
+            .and_then(|v| u32::try_from(v).ok())
+            .context("config.toml: `version` must be a non-negative integer"),
+    }
+}
+
+/// Applies the steps from `version` to [`CONFIG_VERSION`] in memory. Steps
+/// may move data into `data_dir`.
+pub fn upgrade(config: &mut Table, version: u32, data_dir: &Path) -> Result<()> {
+    for (from, step) in STEPS.iter().enumerate().skip(version as usize) {
+        step(config, data_dir)
+            .with_context(|| format!("Failed to migrate config.toml from version {}", from))?;
+    }
+    config.insert("version".to_string(), (CONFIG_VERSION as i64).into());
+    Ok(())
+}
+
+/// 0 -> 1: check results move from config.toml to the state store. State
+/// already in the store wins.
+fn move_runtime_state(config: &mut Table, data_dir: &Path) -> Result<()> {
+    let Some(sites) = config.get_mut("sites").and_then(|s| s.as_array_mut()) else {
+        return Ok(());
+    };
+
+    let store = StateStore::new(data_dir);
+    for site in sites.iter_mut().filter_map(|s| s.as_table_mut()) {
+        let mut runtime = Table::new();
+        for key in RUNTIME_KEYS {
+            if let Some(value) = site.remove(key) {
+                runtime.insert(key.to_string(), value);
+            }
+        }
+
+        let Some(id) = site.get("id").and_then(|id| id.as_str()) else {
+            continue;
+        };
+        if runtime.is_empty() || store.load(id)?.is_some() {
+            continue;
+        }
+
+        let state: SiteState = runtime.try_into()?;
+        store.store(id, &state)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERSION_0: &str = r#"
+        check_interval_secs = 180
+
+        [[sites]]
+        id = "a1b2"
+        url = "https://example.com/a"
+        name = "A"
+        enabled = true
+        last_hash = "abc"
+        last_error = "timeout"
+        muted = true
+
+        [[sites]]
+        id = "c3d4"
+        url = "https://example.com/b"
+        name = "B"
+        enabled = true
+        last_hash = "def"
+        "#;
+
+    #[test]
+    fn upgrade_moves_runtime_state_to_the_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StateStore::new(dir.path());
+        // Already in the store from a newer rnot, so it wins
+        let stored = SiteState {
+            last_hash: Some("newer".to_string()),
+            ..Default::default()
+        };
+        store.store("c3d4", &stored).unwrap();
+
+        let mut config: Table = VERSION_0.parse().unwrap();
+        upgrade(&mut config, 0, dir.path()).unwrap();
+
+        assert_eq!(version_of(&config).unwrap(), CONFIG_VERSION);
+        let sites = config["sites"].as_array().unwrap();
+        for site in sites {
+            let site = site.as_table().unwrap();
+            assert!(RUNTIME_KEYS.iter().all(|key| !site.contains_key(*key)));
+        }
+
+        let a = store.load("a1b2").unwrap().unwrap();
+        assert_eq!(a.last_hash.as_deref(), Some("abc"));
+        assert_eq!(a.last_error.as_deref(), Some("timeout"));
+        assert!(a.muted);
+        let b = store.load("c3d4").unwrap().unwrap();
+        assert_eq!(b.last_hash.as_deref(), Some("newer"));
+
+        // The result is a valid current config
+        crate::config::parse(&toml::to_string(&config).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn run_keeps_a_backup_and_refuses_newer_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_file = dir.path().join("config.toml");
+
+        std::fs::write(&config_file, VERSION_0).unwrap();
+        run(dir.path(), dir.path()).unwrap();
+        let backup = std::fs::read_to_string(dir.path().join("config.toml.v0.bak")).unwrap();
+        assert_eq!(backup, VERSION_0);
+        let migrated: Table = std::fs::read_to_string(&config_file)
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(version_of(&migrated).unwrap(), CONFIG_VERSION);
+
+        std::fs::write(
+            &config_file,
+            "version = 99\ncheck_interval_secs = 180\nsites = []\n",
+        )
+        .unwrap();
+        let error = run(dir.path(), dir.path()).unwrap_err().to_string();
+        assert!(error.contains("version 99"), "{}", error);
+    }
+
+    #[test]
+    fn versions_must_be_non_negative_integers() {
+        assert_eq!(version_of(&Table::new()).unwrap(), 0);
+        assert!(version_of(&"version = -1".parse().unwrap()).is_err());
+        assert!(version_of(&"version = \"1\"".parse().unwrap()).is_err());
+    }
+}
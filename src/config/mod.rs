@@ -1,38 +1,459 @@
+mod migrate;
+mod validate;
+
 use crate::crypto::TokenEncryption;
+use crate::state::StateStore;
 use anyhow::Result;
 use chrono::{DateTime, Utc};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+pub use validate::validate;
+
+/// The config.toml schema version this build writes. Older files are
+/// upgraded on start by `migrate::run`.
+pub const CONFIG_VERSION: u32 = 1;
+
+const DEFAULT_CHECK_INTERVAL_SECS: u64 = 180;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WatchedSite {
     pub id: String,
     pub url: String,
     pub name: String,
+    // Runtime state lives in the state store (see `crate::state`) and is
+    // never read from or written to config.toml
+    #[serde(skip)]
     pub last_hash: Option<String>,
+    #[serde(skip)]
     pub last_checked: Option<DateTime<Utc>>,
+    #[serde(skip)]
     pub last_change: Option<DateTime<Utc>>,
     pub enabled: bool,
     pub css_selector: Option<String>,
+    #[serde(default)]
+    pub track_value: bool,
+    /// Names of the notifiers this site reports to; `None` means all of them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notifiers: Option<Vec<String>>,
+    #[serde(skip)]
+    pub last_error: Option<String>,
+    #[serde(default, skip_serializing_if = "LongDiffMode::is_default")]
+    pub telegram_long_diff: LongDiffMode,
+    /// Change alerts are suppressed while muted.
+    #[serde(skip)]
+    pub muted: bool,
+    /// Change alerts are suppressed until this time.
+    #[serde(skip)]
+    pub snoozed_until: Option<DateTime<Utc>>,
+    /// When the latest change was acknowledged.
+    #[serde(skip)]
+    pub last_seen: Option<DateTime<Utc>>,
+    /// Alerts for urgent sites are sent during quiet hours.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub urgent: bool,
+    /// Keep the raw response of every change so `rnot reprocess` can
+    /// re-extract it later.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub archive_raw: bool,
+    /// Groups the site belongs to; it inherits their `[groups.<tag>]`
+    /// settings.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Seconds between checks, overriding the group and global interval.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check_interval_secs: Option<u64>,
+    /// Regexes for lines to drop before comparing, on top of the built-in
+    /// noise filter and the rules of the site's groups.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore: Vec<String>,
+    /// What the site inherits from its groups; see
+    /// [`AppConfig::apply_groups`].
+    #[serde(skip)]
+    pub inherited: GroupConfig,
+    /// `ignore` and the rules of the site's groups, compiled by
+    /// [`AppConfig::apply_groups`].
+    #[serde(skip)]
+    pub ignore_rules: Vec<Regex>,
+}
+
+/// Settings shared by the sites with a tag, under `[groups.<tag>]`. A
+/// site's own settings take precedence.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GroupConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check_interval_secs: Option<u64>,
+    /// Notifiers for sites that do not name their own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notifiers: Option<Vec<String>>,
+    /// Noise rules added to those of every site in the group.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore: Vec<String>,
+}
+
+/// How Telegram delivers diffs that do not fit in one message.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LongDiffMode {
+    /// Cut the diff to fit a single message
+    #[default]
+    Truncate,
+    /// Continue the diff over a few messages; longer diffs are sent as
+    /// with `Document`
+    Split,
+    /// Send a truncated message plus the full diff as a `.diff` file
+    Document,
+}
+
+impl LongDiffMode {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl WatchedSite {
+    /// An enabled site with default settings and no runtime state.
+    pub fn new(url: String, name: String, css_selector: Option<String>) -> Self {
+        Self {
+            id: generate_id(&url),
+            url,
+            name,
+            last_hash: None,
+            last_checked: None,
+            last_change: None,
+            enabled: true,
+            css_selector,
+            track_value: false,
+            notifiers: None,
+            last_error: None,
+            telegram_long_diff: LongDiffMode::default(),
+            muted: false,
+            snoozed_until: None,
+            last_seen: None,
+            urgent: false,
+            archive_raw: false,
+            tags: Vec::new(),
+            check_interval_secs: None,
+            ignore: Vec::new(),
+            inherited: GroupConfig::default(),
+            ignore_rules: Vec::new(),
+        }
+    }
+
+    pub fn routes_to(&self, notifier: &str) -> bool {
+        match self
+            .notifiers
+            .as_ref()
+            .or(self.inherited.notifiers.as_ref())
+        {
+            Some(names) => names.iter().any(|n| n == notifier),
+            None => true,
+        }
+    }
+
+    /// Seconds between checks: the site's own interval, else that of its
+    /// groups, else `global`, the `check_interval_secs` of config.toml.
+    pub fn interval_secs(&self, global: u64) -> u64 {
+        self.check_interval_secs
+            .or(self.inherited.check_interval_secs)
+            .unwrap_or(global)
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    /// Whether change alerts are currently muted or snoozed.
+    pub fn is_silenced(&self) -> bool {
+        self.muted || self.snoozed_until.is_some_and(|until| until > Utc::now())
+    }
+
+    /// Whether the latest change has not been marked as seen.
+    pub fn has_unseen_change(&self) -> bool {
+        match (self.last_change, self.last_seen) {
+            (Some(change), Some(seen)) => change > seen,
+            (Some(_), None) => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum NotifierConfig {
+    Telegram {
+        name: String,
+        chat_id: String,
+        /// Forum topic to post in, for groups with topics enabled.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        message_thread_id: Option<i64>,
+    },
+    Webhook {
+        name: String,
+        url: String,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        headers: BTreeMap<String, String>,
+        /// JSON body with `{{placeholder}}` substitutions; the default payload if unset.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        template: Option<String>,
+        /// Sign bodies with HMAC-SHA256 using the secret stored under the notifier name.
+        #[serde(default)]
+        sign: bool,
+    },
+    /// SMTP delivery; the password is the secret stored under the notifier name.
+    Email {
+        name: String,
+        host: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        port: Option<u16>,
+        #[serde(default)]
+        security: SmtpSecurity,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        username: Option<String>,
+        from: String,
+        to: Vec<String>,
+    },
+    /// Slack incoming webhook; the webhook URL is the stored secret.
+    Slack { name: String },
+    /// Discord channel webhook; the webhook URL is the stored secret.
+    Discord { name: String },
+    /// Matrix room; the access token is the stored secret.
+    Matrix {
+        name: String,
+        homeserver: String,
+        room_id: String,
+    },
+    /// Native notifications via org.freedesktop.Notifications (Linux only).
+    Desktop { name: String },
+    /// Runs a local program for every event.
+    Command {
+        name: String,
+        program: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        args: Vec<String>,
+        #[serde(default = "default_hook_timeout_secs")]
+        timeout_secs: u64,
+    },
+}
+
+fn default_hook_timeout_secs() -> u64 {
+    30
+}
+
+/// How a notifier delivers change alerts. Errors and recoveries are always
+/// sent immediately.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum DeliveryMode {
+    /// Send each change as it is detected
+    #[default]
+    Immediate,
+    /// Collect changes for `window_mins` after the first, then send them together
+    Batched {
+        #[serde(default = "default_batch_window_mins")]
+        window_mins: u64,
+    },
+    /// Send the changes collected so far once a day at `at` ("HH:MM", local time)
+    Digest { at: String },
+}
+
+fn default_batch_window_mins() -> u64 {
+    15
+}
+
+/// Which backend keeps check history, snapshots and diffs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// Plain files under the data directory
+    #[default]
+    Files,
+    /// One SQLite database (`rnot.db`) in the data directory; needs the
+    /// `sqlite` feature
+    Sqlite,
+}
+
+impl StorageBackend {
+    /// The value of `storage` in config.toml.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Files => "files",
+            Self::Sqlite => "sqlite",
+        }
+    }
+
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// How much of each site's past `rnot gc` keeps. Snapshot versions stay
+/// while a kept check refers to them; the latest one always stays.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Retention {
+    /// Most recent checks kept per site; 0 keeps them all.
+    #[serde(default)]
+    pub max_checks: usize,
+    /// Checks, diffs and archived responses older than this go; 0 keeps
+    /// them forever.
+    #[serde(default = "default_max_age_days")]
+    pub max_age_days: u32,
+}
+
+fn default_max_age_days() -> u32 {
+    90
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            max_checks: 0,
+            max_age_days: default_max_age_days(),
+        }
+    }
+}
+
+impl Retention {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// A daily window during which a notifier holds back alerts for
+/// non-urgent sites.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuietHours {
+    /// "HH:MM"
+    pub start: String,
+    /// "HH:MM"; before `start` for windows spanning midnight
+    pub end: String,
+    /// IANA name such as "Europe/Berlin"; the system timezone if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Implicit TLS, usually port 465
+    Tls,
+    /// Upgrade a plain connection, usually port 587
+    #[default]
+    Starttls,
+    /// No encryption, for local relays and test servers
+    None,
+}
+
+impl NotifierConfig {
+    pub fn name(&self) -> &str {
+        match self {
+            NotifierConfig::Telegram { name, .. } => name,
+            NotifierConfig::Webhook { name, .. } => name,
+            NotifierConfig::Email { name, .. } => name,
+            NotifierConfig::Slack { name } => name,
+            NotifierConfig::Discord { name } => name,
+            NotifierConfig::Matrix { name, .. } => name,
+            NotifierConfig::Desktop { name } => name,
+            NotifierConfig::Command { name, .. } => name,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppConfig {
+    /// Missing in files written before versioning, which count as 0.
+    #[serde(default)]
+    pub version: u32,
     pub telegram_chat_id: Option<String>,
     pub check_interval_secs: u64,
     pub sites: Vec<WatchedSite>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notifiers: Vec<NotifierConfig>,
+    /// Delivery mode per notifier name; unlisted notifiers send immediately.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub delivery: BTreeMap<String, DeliveryMode>,
+    /// Quiet hours per notifier name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub quiet_hours: BTreeMap<String, QuietHours>,
+    #[serde(default, skip_serializing_if = "StorageBackend::is_default")]
+    pub storage: StorageBackend,
+    #[serde(default, skip_serializing_if = "Retention::is_default")]
+    pub retention: Retention,
+    /// Settings per tag, inherited by the sites with that tag.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub groups: BTreeMap<String, GroupConfig>,
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
+            version: CONFIG_VERSION,
             telegram_chat_id: None,
-            check_interval_secs: 180,
+            check_interval_secs: DEFAULT_CHECK_INTERVAL_SECS,
             sites: Vec::new(),
+            notifiers: Vec::new(),
+            delivery: BTreeMap::new(),
+            quiet_hours: BTreeMap::new(),
+            storage: StorageBackend::default(),
+            retention: Retention::default(),
+            groups: BTreeMap::new(),
         }
     }
 }
 
+impl AppConfig {
+    /// Fills in what each site inherits and compiles its noise rules. The
+    /// first of its tags whose group sets a check interval or notifiers
+    /// decides those, and the noise rules of all its groups apply. Fails on
+    /// the first rule that is not a valid regex.
+    pub fn apply_groups(&mut self) -> Result<()> {
+        let mut group_rules = BTreeMap::new();
+        for (tag, group) in &self.groups {
+            let at = format!("groups.{}.ignore", tag);
+            group_rules.insert(tag.as_str(), compile_rules(&at, &group.ignore)?);
+        }
+
+        for (index, site) in self.sites.iter_mut().enumerate() {
+            let mut inherited = GroupConfig::default();
+            let mut rules = compile_rules(&format!("sites[{}].ignore", index), &site.ignore)?;
+            for tag in &site.tags {
+                let Some(group) = self.groups.get(tag) else {
+                    continue;
+                };
+                inherited.check_interval_secs =
+                    inherited.check_interval_secs.or(group.check_interval_secs);
+                if inherited.notifiers.is_none() {
+                    inherited.notifiers = group.notifiers.clone();
+                }
+                inherited.ignore.extend(group.ignore.iter().cloned());
+                rules.extend(group_rules[tag.as_str()].iter().cloned());
+            }
+            site.inherited = inherited;
+            site.ignore_rules = rules;
+        }
+
+        Ok(())
+    }
+
+    /// Every chat alerts go to on Telegram, which are the chats the bot
+    /// takes commands and button presses from.
+    pub fn telegram_chats(&self) -> Vec<String> {
+        let mut chats: Vec<String> = self.telegram_chat_id.iter().cloned().collect();
+        for notifier in &self.notifiers {
+            if let NotifierConfig::Telegram { chat_id, .. } = notifier {
+                if !chats.contains(chat_id) {
+                    chats.push(chat_id.clone());
+                }
+            }
+        }
+        chats
+    }
+}
+
 pub struct Config {
     pub config_dir: PathBuf,
     pub data_dir: PathBuf,
@@ -43,27 +464,16 @@ pub struct Config {
 
 impl Config {
     pub fn new() -> Result<Self> {
-        let config_dir = dirs::config_dir()
-            .unwrap_or_else(|| PathBuf::from("."))
-            .join("rnot");
-
-        let data_dir = dirs::data_dir()
-            .unwrap_or_else(|| PathBuf::from("."))
-            .join("rnot");
+        let config_dir = config_dir();
+        let data_dir = data_dir();
 
         std::fs::create_dir_all(&config_dir)?;
         std::fs::create_dir_all(&data_dir)?;
 
-        let config_file = config_dir.join("config.toml");
-        let app_config = if config_file.exists() {
-            let content = std::fs::read_to_string(&config_file)?;
-            toml::from_str(&content)?
-        } else {
-            let default_config = AppConfig::default();
-            let content = toml::to_string_pretty(&default_config)?;
-            std::fs::write(&config_file, content)?;
-            default_config
-        };
+        if !config_dir.join("config.toml").exists() {
+            modify_app_config(&config_dir, |_| Ok(()))?;
+        }
+        let app_config = open(&config_dir, &data_dir)?;
 
         let encryption = TokenEncryption::new(config_dir.clone());
         let cached_token = None;
@@ -77,6 +487,22 @@ impl Config {
         })
     }
 
+    /// Reads config.toml and fills in each site's runtime state.
+    fn load(config_dir: &Path, data_dir: &Path) -> Result<AppConfig> {
+        let content = std::fs::read_to_string(config_dir.join("config.toml"))?;
+        let mut app_config = parse(&content)?;
+        StateStore::new(data_dir).apply_all(&mut app_config.sites);
+        app_config.apply_groups()?;
+        Ok(app_config)
+    }
+
+    /// Re-reads config.toml and the state store, picking up changes written
+    /// by other processes.
+    pub fn reload(&mut self) -> Result<()> {
+        self.app_config = Self::load(&self.config_dir, &self.data_dir)?;
+        Ok(())
+    }
+
     pub fn get_telegram_token(&mut self) -> Option<String> {
         if let Some(ref token) = self.cached_token {
             return Some(token.clone());
@@ -123,78 +549,510 @@ impl Config {
         self.get_telegram_token().is_some()
     }
 
-    pub fn save(&self) -> Result<()> {
-        let config_file = self.config_dir.join("config.toml");
-        let content = toml::to_string_pretty(&self.app_config)?;
-        std::fs::write(&config_file, content)?;
+    /// Whether a token is stored, even one that cannot be decrypted.
+    pub fn stores_telegram_token(&self) -> bool {
+        self.config_dir.join(".token").exists()
+    }
+
+    /// Reads a notifier secret (password, signing key, access token) by name.
+    pub fn get_secret(&self, name: &str) -> Option<String> {
+        let secret_file = self.secret_file(name).ok()?;
+        let encrypted = std::fs::read_to_string(secret_file).ok()?;
+        self.encryption.decrypt(encrypted.trim()).ok()
+    }
+
+    pub fn set_secret(&self, name: &str, value: &str) -> Result<()> {
+        let secret_file = self.secret_file(name)?;
+        if let Some(parent) = secret_file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let encrypted = self.encryption.encrypt(value)?;
+        std::fs::write(&secret_file, &encrypted)?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&secret_file, std::fs::Permissions::from_mode(0o600))?;
+        }
+
         Ok(())
     }
 
+    pub fn clear_secret(&self, name: &str) -> Result<bool> {
+        let secret_file = self.secret_file(name)?;
+        if secret_file.exists() {
+            std::fs::remove_file(&secret_file)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Names of the stored notifier secrets, sorted.
+    pub fn secret_names(&self) -> Result<Vec<String>> {
+        let dir = self.config_dir.join(".secrets");
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if self.secret_file(&name).is_ok() {
+                names.push(name);
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn secret_file(&self, name: &str) -> Result<PathBuf> {
+        if name.is_empty()
+            || name.len() > 64
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            anyhow::bail!("Secret names may only contain letters, digits, '-' and '_'");
+        }
+        Ok(self.config_dir.join(".secrets").join(name))
+    }
+
+    /// Applies `f` to the current config.toml and saves the result; see
+    /// [`modify_app_config`]. Our copy is refreshed along the way.
+    pub fn modify<R>(&mut self, f: impl FnOnce(&mut AppConfig) -> Result<R>) -> Result<R> {
+        let (mut app_config, result) = modify_app_config(&self.config_dir, f)?;
+        StateStore::new(&self.data_dir).apply_all(&mut app_config.sites);
+        self.app_config = app_config;
+        Ok(result)
+    }
+
     pub fn add_site(
         &mut self,
         url: String,
         name: String,
         css_selector: Option<String>,
     ) -> Result<String> {
-        // Validate URL
-        url::Url::parse(&url).map_err(|e| anyhow::anyhow!("Invalid URL: {}", e))?;
-
-        // Validate name
-        if name.is_empty() || name.len() > 255 {
-            anyhow::bail!("Site name must be between 1 and 255 characters");
-        }
+        validate_site(&url, &name, css_selector.as_deref())?;
 
-        // Validate CSS selector if provided
-        if let Some(ref selector) = css_selector {
-            if selector.len() > 1024 {
-                anyhow::bail!("CSS selector too long (max 1024 characters)");
-            }
-        }
-
-        let id = self.generate_id(&url);
-        let site = WatchedSite {
-            id: id.clone(),
-            url,
-            name,
-            last_hash: None,
-            last_checked: None,
-            last_change: None,
-            enabled: true,
-            css_selector,
-        };
-        self.app_config.sites.push(site);
-        self.save()?;
+        let site = WatchedSite::new(url, name, css_selector);
+        let id = site.id.clone();
+        self.modify(|config| {
+            config.sites.push(site);
+            Ok(())
+        })?;
         Ok(id)
     }
 
     pub fn remove_site(&mut self, id: &str) -> Result<bool> {
-        let initial_len = self.app_config.sites.len();
-        self.app_config.sites.retain(|s| s.id != id && s.url != id);
-        if self.app_config.sites.len() < initial_len {
-            self.save()?;
-            return Ok(true);
+        let removed = self.remove_sites(|s| s.id == id || s.url == id)?;
+        Ok(!removed.is_empty())
+    }
+
+    /// Removes every site `matches` selects, returning them.
+    pub fn remove_sites(
+        &mut self,
+        matches: impl Fn(&WatchedSite) -> bool,
+    ) -> Result<Vec<WatchedSite>> {
+        let removed: Vec<WatchedSite> = self.modify(|config| {
+            let (removed, kept) = std::mem::take(&mut config.sites)
+                .into_iter()
+                .partition(|s| matches(s));
+            config.sites = kept;
+            Ok(removed)
+        })?;
+
+        let state = StateStore::new(&self.data_dir);
+        for site in &removed {
+            state.remove(&site.id)?;
         }
-        Ok(false)
+        Ok(removed)
     }
 
     pub fn set_telegram_chat_id(&mut self, chat_id: String) -> Result<()> {
-        self.app_config.telegram_chat_id = Some(chat_id);
-        self.save()
+        self.modify(|config| {
+            config.telegram_chat_id = Some(chat_id);
+            Ok(())
+        })
+    }
+
+    /// Adds a named Telegram destination, replacing one with the same name.
+    pub fn set_telegram_destination(
+        &mut self,
+        name: &str,
+        chat_id: String,
+        message_thread_id: Option<i64>,
+    ) -> Result<()> {
+        let destination = NotifierConfig::Telegram {
+            name: name.to_string(),
+            chat_id,
+            message_thread_id,
+        };
+
+        self.modify(|config| {
+            match config.notifiers.iter_mut().find(|n| n.name() == name) {
+                Some(existing @ NotifierConfig::Telegram { .. }) => *existing = destination,
+                Some(_) => anyhow::bail!("Notifier '{}' exists and is not a Telegram chat", name),
+                None => config.notifiers.push(destination),
+            }
+            Ok(())
+        })
+    }
+
+    /// Saves the check results of `site` without touching config.toml or
+    /// the mute, snooze and last seen time.
+    pub fn save_state(&mut self, site: &WatchedSite) -> Result<()> {
+        StateStore::new(&self.data_dir).save_checks(site)?;
+        if let Some(existing) = self.app_config.sites.iter_mut().find(|s| s.id == site.id) {
+            existing.last_hash = site.last_hash.clone();
+            existing.last_checked = site.last_checked;
+            existing.last_change = site.last_change;
+            existing.last_error = site.last_error.clone();
+        }
+        Ok(())
     }
 
-    pub fn update_site(&mut self, site: &WatchedSite) -> Result<()> {
+    /// Saves the mute, snooze and last seen time of `site`, leaving its
+    /// check results as they are.
+    pub fn save_alert_state(&mut self, site: &WatchedSite) -> Result<()> {
+        StateStore::new(&self.data_dir).save_alerts(site)?;
         if let Some(existing) = self.app_config.sites.iter_mut().find(|s| s.id == site.id) {
-            *existing = site.clone();
-            self.save()?;
+            existing.muted = site.muted;
+            existing.snoozed_until = site.snoozed_until;
+            existing.last_seen = site.last_seen;
         }
         Ok(())
     }
+}
+
+/// The ID of a site is a hash of its URL, so the same URL always gets the
+/// same ID.
+pub fn generate_id(url: &str) -> String {
+    use sha2::{Digest, Sha256};
+    let mut hasher = Sha256::new();
+    hasher.update(url.as_bytes());
+    let result = hasher.finalize();
+    hex::encode(&result[..8])
+}
+
+/// The checks `Config::add_site` applies to a new site.
+pub fn validate_site(url: &str, name: &str, css_selector: Option<&str>) -> Result<()> {
+    // Validate URL
+    let parsed = url::Url::parse(url).map_err(|e| anyhow::anyhow!("Invalid URL: {}", e))?;
+    if parsed.scheme() != "http" && parsed.scheme() != "https" {
+        anyhow::bail!("Only HTTP and HTTPS URLs are supported");
+    }
+
+    // Validate name
+    if name.is_empty() || name.len() > 255 {
+        anyhow::bail!("Site name must be between 1 and 255 characters");
+    }
+
+    // Validate CSS selector if provided
+    if let Some(selector) = css_selector {
+        if selector.len() > 1024 {
+            anyhow::bail!("CSS selector too long (max 1024 characters)");
+        }
+    }
+
+    Ok(())
+}
+
+/// Tags name groups in config.toml and are given on the command line, so
+/// they are kept to letters, digits, '-' and '_'.
+pub fn validate_tag(tag: &str) -> Result<()> {
+    if tag.is_empty()
+        || tag.len() > 64
+        || !tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        anyhow::bail!(
+            "Invalid tag {:?}: use 1 to 64 letters, digits, '-' and '_'",
+            tag
+        );
+    }
+    Ok(())
+}
+
+/// Where config.toml and the encrypted secrets live.
+pub fn config_dir() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("rnot")
+}
+
+pub fn data_dir() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("rnot")
+}
+
+/// Applies `f` to config.toml in `config_dir` (or to the defaults if it does
+/// not exist yet) and writes the result back, returning the new config,
+/// with group settings applied, along with `f`'s result.
+///
+/// Every writer goes through here. An exclusive lock on `config.lock` keeps
+/// the daemon, the TUI and one-off commands from losing each other's
+/// updates, and the file is replaced atomically so a crash never leaves it
+/// half-written. Nothing is written if `f` fails or leaves a config that
+/// does not load, such as one with an invalid ignore rule.
+pub fn modify_app_config<R>(
+    config_dir: &Path,
+    f: impl FnOnce(&mut AppConfig) -> Result<R>,
+) -> Result<(AppConfig, R)> {
+    let lock = lock_config(config_dir)?;
+
+    let config_file = config_dir.join("config.toml");
+    let mut app_config = if config_file.exists() {
+        parse(&std::fs::read_to_string(&config_file)?)?
+    } else {
+        AppConfig::default()
+    };
+
+    let result = f(&mut app_config)?;
+    app_config.apply_groups()?;
+    write_config(config_dir, &toml::to_string_pretty(&app_config)?)?;
+
+    // Released when `lock` is dropped, but only after the rename
+    drop(lock);
+
+    Ok((app_config, result))
+}
+
+/// Replaces config.toml in `config_dir` with `content` as is, for files
+/// written by another rnot. `content` is not checked; it is migrated and
+/// parsed on the next load.
+pub fn replace_config(config_dir: &Path, content: &str) -> Result<()> {
+    let _lock = lock_config(config_dir)?;
+    write_config(config_dir, content)
+}
+
+/// Takes the exclusive lock every writer of config.toml holds; it is
+/// released when the returned file is dropped.
+pub fn lock_config(config_dir: &Path) -> Result<std::fs::File> {
+    std::fs::create_dir_all(config_dir)?;
+
+    let lock = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(config_dir.join("config.lock"))?;
+    lock.lock()?;
+
+    Ok(lock)
+}
+
+/// Atomically replaces config.toml. Callers must hold the config lock.
+fn write_config(config_dir: &Path, content: &str) -> Result<()> {
+    let mut temp = tempfile::NamedTempFile::new_in(config_dir)?;
+    temp.write_all(content.as_bytes())?;
+    temp.as_file().sync_all()?;
+    temp.persist(config_dir.join("config.toml"))?;
+    Ok(())
+}
+
+/// Compiles the noise rules found at `at` in config.toml, failing on the
+/// first that is not a valid regex.
+pub fn compile_rules(at: &str, rules: &[String]) -> Result<Vec<Regex>> {
+    rules
+        .iter()
+        .map(|rule| {
+            Regex::new(rule).map_err(|e| anyhow::anyhow!("{}: invalid regex {:?}: {}", at, rule, e))
+        })
+        .collect()
+}
+
+/// Upgrades config.toml in `config_dir` if it is from an older rnot and
+/// loads it with the state in `data_dir`.
+pub fn open(config_dir: &Path, data_dir: &Path) -> Result<AppConfig> {
+    migrate::run(config_dir, data_dir)?;
+    Config::load(config_dir, data_dir)
+}
+
+/// Parses config.toml, reporting the line, column and field of the first
+/// problem.
+pub fn parse(content: &str) -> Result<AppConfig> {
+    parse_with_unknown(content, |_| {})
+}
+
+/// Like [`parse`], also calling `unknown` with each key that is not part
+/// of the schema.
+fn parse_with_unknown(
+    content: &str,
+    mut unknown: impl FnMut(serde_ignored::Path),
+) -> Result<AppConfig> {
+    let deserializer =
+        serde_ignored::Deserializer::new(toml::Deserializer::new(content), &mut unknown);
+    serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let field = e.path().to_string();
+        let error = e.into_inner();
+        let field = (field != ".").then_some(field);
+        describe_error(content, error.span(), field.as_deref(), error.message())
+    })
+}
+
+fn describe_error(
+    content: &str,
+    span: Option<Range<usize>>,
+    field: Option<&str>,
+    message: &str,
+) -> anyhow::Error {
+    let mut location = "config.toml".to_string();
+
+    if let Some(span) = span {
+        let before = content.get(..span.start).unwrap_or(content);
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+        location.push_str(&format!(" line {}, column {}", line, column));
+    }
+    if let Some(field) = field {
+        location.push_str(&format!(", field `{}`", field));
+    }
+
+    anyhow::anyhow!("Invalid {}: {}", location, message.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn telegram_chats_cover_every_destination() {
+        let config = parse(
+            r#"
+            check_interval_secs = 180
+            telegram_chat_id = "1"
+            sites = []
+
+            [[notifiers]]
+            type = "telegram"
+            name = "team"
+            chat_id = "-100"
+
+            [[notifiers]]
+            type = "telegram"
+            name = "topic"
+            chat_id = "-100"
+            message_thread_id = 7
+
+            [[notifiers]]
+            type = "telegram"
+            name = "telegram"
+            chat_id = "1"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.telegram_chats(), ["1", "-100"]);
+        assert!(AppConfig::default().telegram_chats().is_empty());
+    }
+
+    const GROUPS: &str = r#"
+        check_interval_secs = 300
+
+        [[sites]]
+        id = "a1"
+        url = "https://example.com/a"
+        name = "A"
+        enabled = true
+        tags = ["news", "shops"]
+        ignore = ["^Visitors"]
+
+        [[sites]]
+        id = "b2"
+        url = "https://example.com/b"
+        name = "B"
+        enabled = true
+        tags = ["shops"]
+        check_interval_secs = 60
+        notifiers = ["mail"]
+
+        [[sites]]
+        id = "c3"
+        url = "https://example.com/c"
+        name = "C"
+        enabled = true
+
+        [groups.news]
+        check_interval_secs = 900
+        ignore = ["^Updated"]
+
+        [groups.shops]
+        check_interval_secs = 3600
+        notifiers = ["team"]
+        ignore = ["^In stock: \\d+"]
+        "#;
+
+    #[test]
+    fn sites_inherit_from_their_groups() {
+        let mut config = parse(GROUPS).unwrap();
+        config.apply_groups().unwrap();
+        let [a, b, c] = &config.sites[..] else {
+            panic!("three sites expected");
+        };
+
+        // The first group setting an interval or notifiers decides them
+        assert_eq!(a.interval_secs(300), 900);
+        assert!(a.routes_to("team") && !a.routes_to("mail"));
+        let rules: Vec<&str> = a.ignore_rules.iter().map(Regex::as_str).collect();
+        assert_eq!(rules, ["^Visitors", "^Updated", r"^In stock: \d+"]);
+
+        // A site's own settings win
+        assert_eq!(b.interval_secs(300), 60);
+        assert!(b.routes_to("mail") && !b.routes_to("team"));
+
+        assert_eq!(c.interval_secs(300), 300);
+        assert!(c.routes_to("anything"));
+        assert!(c.ignore_rules.is_empty());
+    }
+
+    #[test]
+    fn invalid_ignore_rules_are_rejected_on_load() {
+        let mut config = parse(&GROUPS.replace("^Updated", "(Updated")).unwrap();
+        let error = config.apply_groups().unwrap_err().to_string();
+        assert!(
+            error.starts_with("groups.news.ignore: invalid regex"),
+            "{}",
+            error
+        );
+
+        let mut config = parse(&GROUPS.replace("^Visitors", "[Visitors")).unwrap();
+        let error = config.apply_groups().unwrap_err().to_string();
+        assert!(
+            error.starts_with("sites[0].ignore: invalid regex"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn sites_without_groups_applied_use_the_given_interval() {
+        let site = WatchedSite::new("https://example.com".to_string(), "Ex".to_string(), None);
+        assert_eq!(site.interval_secs(600), 600);
+    }
+
+    #[test]
+    fn rejected_changes_leave_config_toml_as_it_was() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = format!("# Hand-written\n{}", GROUPS);
+        std::fs::write(dir.path().join("config.toml"), &content).unwrap();
+
+        let result = modify_app_config(dir.path(), |config| {
+            let mut site =
+                WatchedSite::new("https://example.org".to_string(), "O".to_string(), None);
+            site.ignore = vec!["(".to_string()];
+            config.sites.push(site);
+            Ok(())
+        });
 
-    fn generate_id(&self, url: &str) -> String {
-        use sha2::{Digest, Sha256};
-        let mut hasher = Sha256::new();
-        hasher.update(url.as_bytes());
-        let result = hasher.finalize();
-        hex::encode(&result[..8])
+        let error = result.unwrap_err().to_string();
+        assert!(
+            error.starts_with("sites[3].ignore: invalid regex"),
+            "{}",
+            error
+        );
+        let after = std::fs::read_to_string(dir.path().join("config.toml")).unwrap();
+        assert_eq!(after, content);
     }
 }
@@ -0,0 +1,399 @@
+use super::{migrate, parse_with_unknown, AppConfig, WatchedSite, CONFIG_VERSION};
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use std::collections::{HashMap, HashSet};
+use toml::{Table, Value};
+
+/// Checking more often than this mostly gets the monitor rate limited.
+pub const MIN_CHECK_INTERVAL_SECS: u64 = 60;
+
+/// Everything wrong with a config.toml. Errors make checks or
+/// notifications fail at runtime; warnings are lint that rnot tolerates.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+/// Checks a config.toml without loading it, collecting every problem
+/// rather than stopping at the first.
+pub fn validate(content: &str) -> Report {
+    let mut report = Report::default();
+
+    // Only problems that keep the rest from being checked end up here
+    if let Err(e) = check(content, &mut report) {
+        report.errors.push(e.to_string());
+    }
+
+    report
+}
+
+fn check(content: &str, report: &mut Report) -> Result<()> {
+    let mut table: Table = content.parse().map_err(|e: toml::de::Error| {
+        super::describe_error(content, e.span(), None, e.message())
+    })?;
+
+    let version = migrate::version_of(&table)?;
+    if version > CONFIG_VERSION {
+        anyhow::bail!(
+            "version {} is newer than this rnot understands ({})",
+            version,
+            CONFIG_VERSION
+        );
+    }
+
+    let original = content;
+    let content = if version < CONFIG_VERSION {
+        report.warnings.push(format!(
+            "version: {} is outdated, rnot upgrades it to {} on its next start",
+            version, CONFIG_VERSION
+        ));
+
+        // Steps may move data out of the file; keep it away from ours
+        let scratch = tempfile::tempdir()?;
+        migrate::upgrade(&mut table, version, scratch.path())?;
+        toml::to_string_pretty(&table)?
+    } else {
+        content.to_string()
+    };
+
+    let mut unknown = Vec::new();
+    let (config, sites) =
+        match parse_with_unknown(&content, |path| unknown.push(format_path(&path))) {
+            Ok(mut config) => {
+                let sites = std::mem::take(&mut config.sites);
+                (config, sites.into_iter().enumerate().collect())
+            }
+            // Find what is wrong with every site, not just the first
+            Err(e) => {
+                unknown.clear();
+                parse_by_site(table, &mut unknown, &mut report.errors).ok_or_else(|| {
+                    // Line numbers in the original are the ones users can
+                    // look up, not those of the migrated form
+                    if version < CONFIG_VERSION {
+                        super::parse(original).err().unwrap_or(e)
+                    } else {
+                        e
+                    }
+                })?
+            }
+        };
+
+    for path in unknown {
+        report.warnings.push(format!("{}: unknown key", path));
+    }
+
+    check_interval(
+        "check_interval_secs",
+        Some(config.check_interval_secs),
+        report,
+    );
+
+    let mut notifiers: HashSet<&str> = config.notifiers.iter().map(|n| n.name()).collect();
+    if config.telegram_chat_id.is_some() {
+        notifiers.insert("telegram");
+    }
+
+    for (tag, group) in &config.groups {
+        let at = |field: &str| format!("groups.{}.{}", tag, field);
+
+        if super::validate_tag(tag).is_err() {
+            report.errors.push(format!(
+                "groups.{}: group names must be 1 to 64 letters, digits, '-' and '_'",
+                tag
+            ));
+        }
+        if !sites.iter().any(|(_, site)| site.has_tag(tag)) {
+            report
+                .warnings
+                .push(format!("groups.{}: no site has this tag", tag));
+        }
+        check_interval(
+            &at("check_interval_secs"),
+            group.check_interval_secs,
+            report,
+        );
+        for name in group.notifiers.iter().flatten() {
+            if !notifiers.contains(name.as_str()) {
+                report
+                    .errors
+                    .push(format!("{}: no notifier named {:?}", at("notifiers"), name));
+            }
+        }
+        check_ignore_rules(&at("ignore"), &group.ignore, report);
+    }
+
+    let mut ids = HashMap::new();
+    let mut urls = HashMap::new();
+
+    for (i, site) in &sites {
+        let at = |field: &str| format!("sites[{}].{}", i, field);
+
+        for tag in &site.tags {
+            if let Err(e) = super::validate_tag(tag) {
+                report.errors.push(format!("{}: {}", at("tags"), e));
+            }
+        }
+        check_interval(&at("check_interval_secs"), site.check_interval_secs, report);
+        check_ignore_rules(&at("ignore"), &site.ignore, report);
+
+        let errors = &mut report.errors;
+
+        if site.id.is_empty()
+            || site.id.len() > 64
+            || !site.id.chars().all(|c| c.is_ascii_alphanumeric())
+        {
+            errors.push(format!(
+                "{}: {:?} must be 1 to 64 letters and digits",
+                at("id"),
+                site.id
+            ));
+        }
+        if let Some(first) = ids.get(site.id.as_str()) {
+            errors.push(format!(
+                "{}: {:?} is also the ID of sites[{}]",
+                at("id"),
+                site.id,
+                first
+            ));
+        } else {
+            ids.insert(site.id.as_str(), *i);
+        }
+
+        match url::Url::parse(&site.url) {
+            Err(e) => errors.push(format!("{}: invalid URL {:?}: {}", at("url"), site.url, e)),
+            Ok(url) if url.scheme() != "http" && url.scheme() != "https" => errors.push(format!(
+                "{}: {} URLs cannot be checked, only http and https",
+                at("url"),
+                url.scheme()
+            )),
+            Ok(_) => {}
+        }
+        if let Some(first) = urls.get(site.url.as_str()) {
+            errors.push(format!("{}: also watched by sites[{}]", at("url"), first));
+        } else {
+            urls.insert(site.url.as_str(), *i);
+        }
+
+        if site.name.is_empty() || site.name.len() > 255 {
+            errors.push(format!("{}: must be 1 to 255 characters", at("name")));
+        }
+
+        if let Some(selector) = &site.css_selector {
+            if selector.len() > 1024 {
+                errors.push(format!(
+                    "{}: longer than 1024 characters",
+                    at("css_selector")
+                ));
+            } else if scraper::Selector::parse(selector).is_err() {
+                errors.push(format!(
+                    "{}: invalid CSS selector {:?}",
+                    at("css_selector"),
+                    selector
+                ));
+            }
+        }
+
+        for name in site.notifiers.iter().flatten() {
+            if !notifiers.contains(name.as_str()) {
+                errors.push(format!("{}: no notifier named {:?}", at("notifiers"), name));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Loads the sites of `config` one by one, reporting those that do not load
+/// to `errors` and leaving them out. Returns `None` if the problem is not
+/// in the sites.
+fn parse_by_site(
+    mut config: Table,
+    unknown: &mut Vec<String>,
+    errors: &mut Vec<String>,
+) -> Option<(AppConfig, Vec<(usize, WatchedSite)>)> {
+    let Some(Value::Array(sites)) = config.insert("sites".to_string(), Value::Array(Vec::new()))
+    else {
+        return None;
+    };
+    let config = deserialize(Value::Table(config), "", unknown).ok()?;
+
+    let mut parsed = Vec::new();
+    for (i, site) in sites.into_iter().enumerate() {
+        match deserialize(site, &format!("sites[{}]", i), unknown) {
+            Ok(site) => parsed.push((i, site)),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    Some((config, parsed))
+}
+
+/// Deserializes `value`, found at path `at`, collecting its unknown keys.
+/// Errors name the field they are about.
+fn deserialize<T: DeserializeOwned>(
+    value: Value,
+    at: &str,
+    unknown: &mut Vec<String>,
+) -> std::result::Result<T, String> {
+    let join = |path: &str| match (at, path) {
+        (at, "" | ".") => at.to_string(),
+        ("", path) => path.to_string(),
+        (at, path) => format!("{}.{}", at, path),
+    };
+
+    let mut found = Vec::new();
+    let mut track = |path: serde_ignored::Path| found.push(format_path(&path));
+    let deserializer = serde_ignored::Deserializer::new(value, &mut track);
+    let result = serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let field = join(&e.path().to_string());
+        format!("{}: {}", field, e.into_inner().message())
+    });
+
+    unknown.extend(found.iter().map(|path| join(path)));
+    result
+}
+
+fn check_interval(at: &str, interval_secs: Option<u64>, report: &mut Report) {
+    if let Some(secs) = interval_secs.filter(|secs| *secs < MIN_CHECK_INTERVAL_SECS) {
+        report.warnings.push(format!(
+            "{}: {} is below the minimum of {} seconds",
+            at, secs, MIN_CHECK_INTERVAL_SECS
+        ));
+    }
+}
+
+fn check_ignore_rules(at: &str, rules: &[String], report: &mut Report) {
+    for rule in rules {
+        if regex::Regex::new(rule).is_err() {
+            report
+                .errors
+                .push(format!("{}: invalid regex {:?}", at, rule));
+        }
+    }
+}
+
+/// Formats a path like `sites[2].css_selecter`, as in parse errors.
+fn format_path(path: &serde_ignored::Path) -> String {
+    use serde_ignored::Path;
+
+    match path {
+        Path::Root => String::new(),
+        Path::Seq { parent, index } => format!("{}[{}]", format_path(parent), index),
+        Path::Map { parent, key } => match format_path(parent) {
+            parent if parent.is_empty() => key.clone(),
+            parent => format!("{}.{}", parent, key),
+        },
+        Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => format_path(parent),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "version = 1\ncheck_interval_secs = 180\n";
+
+    fn site(id: &str, url: &str, extra: &str) -> String {
+        format!(
+            "[[sites]]\nid = \"{}\"\nurl = \"{}\"\nname = \"{}\"\nenabled = true\n{}\n",
+            id, url, id, extra
+        )
+    }
+
+    #[test]
+    fn a_valid_config_has_no_problems() {
+        let content = format!("{}{}", HEADER, site("a1", "https://example.com", ""));
+        let report = validate(&content);
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+    }
+
+    #[test]
+    fn every_bad_site_is_reported() {
+        let content = format!(
+            "{}{}{}{}",
+            HEADER,
+            site(
+                "a1",
+                "https://example.com",
+                "check_interval_secs = \"often\""
+            ),
+            site("b2", "ftp://example.com", "css_selecter = \"main\""),
+            site("c3", "https://example.org", "tags = \"oops\""),
+        );
+        let report = validate(&content);
+
+        let errors = report.errors.join("\n");
+        assert!(
+            errors.contains("sites[0].check_interval_secs"),
+            "{}",
+            errors
+        );
+        assert!(errors.contains("sites[1].url: ftp URLs"), "{}", errors);
+        assert!(errors.contains("sites[2].tags"), "{}", errors);
+        assert_eq!(report.errors.len(), 3, "{}", errors);
+        assert_eq!(report.warnings, ["sites[1].css_selecter: unknown key"]);
+    }
+
+    #[test]
+    fn problems_outside_sites_keep_their_line() {
+        let content = format!("{}storage = 5\n{}", HEADER, site("a1", "https://x.io", ""));
+        let report = validate(&content);
+        assert_eq!(report.errors.len(), 1);
+        assert!(report.errors[0].contains("line 3"), "{}", report.errors[0]);
+    }
+
+    #[test]
+    fn lint_is_reported_as_warnings() {
+        let content = format!(
+            "version = 1\ncheck_interval_secs = 10\ncolour = true\n{}{}",
+            site("a1", "https://example.com", ""),
+            site("a1", "https://example.com", "ignore = [\"(\"]"),
+        );
+        let report = validate(&content);
+
+        assert_eq!(
+            report.warnings,
+            [
+                "colour: unknown key",
+                "check_interval_secs: 10 is below the minimum of 60 seconds"
+            ]
+        );
+        let errors = report.errors.join("\n");
+        assert!(
+            errors.contains("sites[1].ignore: invalid regex"),
+            "{}",
+            errors
+        );
+        assert!(errors.contains("sites[1].id: \"a1\" is also the ID of sites[0]"));
+        assert!(errors.contains("sites[1].url: also watched by sites[0]"));
+    }
+
+    #[test]
+    fn outdated_and_newer_versions() {
+        let report = validate(&format!(
+            "check_interval_secs = 180\n{}",
+            site("a1", "https://example.com", "last_hash = \"abc\"")
+        ));
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].starts_with("version: 0 is outdated"));
+
+        let report = validate("version = 99\ncheck_interval_secs = 180\nsites = []\n");
+        assert!(report.errors[0].contains("newer"));
+    }
+
+    #[test]
+    fn outdated_configs_keep_their_own_lines() {
+        let content = format!(
+            "# Written by hand\n\ncheck_interval_secs = 180\nstorage = 5\n{}",
+            site("a1", "https://example.com", "last_hash = \"abc\"")
+        );
+        let report = validate(&content);
+        assert_eq!(report.errors.len(), 1);
+        assert!(report.errors[0].contains("line 4"), "{}", report.errors[0]);
+    }
+}
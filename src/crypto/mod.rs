@@ -4,11 +4,70 @@ use aes_gcm::{
 };
 use anyhow::Result;
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use hmac::{Hmac, Mac};
 use rand::RngCore;
+use sha2::Sha256;
 use std::path::PathBuf;
 
 const NONCE_SIZE: usize = 12;
 
+/// PBKDF2 rounds for keys derived from a passphrase, as OWASP recommends
+/// for HMAC-SHA256.
+pub const PASSPHRASE_ROUNDS: u32 = 600_000;
+
+/// HMAC-SHA256 (RFC 2104).
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().into()
+}
+
+/// Derives a 256-bit key from a passphrase with PBKDF2-HMAC-SHA256.
+pub fn derive_key(passphrase: &str, salt: &[u8], rounds: u32) -> [u8; 32] {
+    pbkdf2::pbkdf2_hmac_array::<Sha256, 32>(passphrase.as_bytes(), salt, rounds)
+}
+
+/// Encrypts with AES-256-GCM under `key`; the random nonce is prepended and
+/// the result base64 encoded.
+pub fn encrypt_with_key(key: &[u8; 32], plaintext: &str) -> Result<String> {
+    let cipher = Aes256Gcm::new_from_slice(key)?;
+
+    let mut nonce_bytes = [0u8; NONCE_SIZE];
+    rand::thread_rng().fill_bytes(&mut nonce_bytes);
+    let nonce = Nonce::from_slice(&nonce_bytes);
+
+    let ciphertext = cipher
+        .encrypt(nonce, plaintext.as_bytes())
+        .map_err(|e| anyhow::anyhow!("Encryption failed: {:?}", e))?;
+
+    let mut result = nonce_bytes.to_vec();
+    result.extend(ciphertext);
+
+    Ok(BASE64.encode(&result))
+}
+
+/// Reverses [`encrypt_with_key`]. Fails if the data was encrypted under
+/// another key or has been tampered with.
+pub fn decrypt_with_key(key: &[u8; 32], encrypted: &str) -> Result<String> {
+    let cipher = Aes256Gcm::new_from_slice(key)?;
+
+    let decoded = BASE64.decode(encrypted)?;
+
+    if decoded.len() < NONCE_SIZE {
+        return Err(anyhow::anyhow!("Invalid encrypted data"));
+    }
+
+    let (nonce_bytes, ciphertext) = decoded.split_at(NONCE_SIZE);
+    let nonce = Nonce::from_slice(nonce_bytes);
+
+    let plaintext = cipher
+        .decrypt(nonce, ciphertext)
+        .map_err(|e| anyhow::anyhow!("Decryption failed: {:?}", e))?;
+
+    Ok(String::from_utf8(plaintext)?)
+}
+
 pub struct TokenEncryption {
     key_file: PathBuf,
 }
@@ -21,41 +80,11 @@ impl TokenEncryption {
     }
 
     pub fn encrypt(&self, plaintext: &str) -> Result<String> {
-        let key = self.get_or_create_key()?;
-        let cipher = Aes256Gcm::new_from_slice(&key)?;
-
-        let mut nonce_bytes = [0u8; NONCE_SIZE];
-        rand::thread_rng().fill_bytes(&mut nonce_bytes);
-        let nonce = Nonce::from_slice(&nonce_bytes);
-
-        let ciphertext = cipher
-            .encrypt(nonce, plaintext.as_bytes())
-            .map_err(|e| anyhow::anyhow!("Encryption failed: {:?}", e))?;
-
-        let mut result = nonce_bytes.to_vec();
-        result.extend(ciphertext);
-
-        Ok(BASE64.encode(&result))
+        encrypt_with_key(&self.get_or_create_key()?, plaintext)
     }
 
     pub fn decrypt(&self, encrypted: &str) -> Result<String> {
-        let key = self.get_or_create_key()?;
-        let cipher = Aes256Gcm::new_from_slice(&key)?;
-
-        let decoded = BASE64.decode(encrypted)?;
-
-        if decoded.len() < NONCE_SIZE {
-            return Err(anyhow::anyhow!("Invalid encrypted data"));
-        }
-
-        let (nonce_bytes, ciphertext) = decoded.split_at(NONCE_SIZE);
-        let nonce = Nonce::from_slice(nonce_bytes);
-
-        let plaintext = cipher
-            .decrypt(nonce, ciphertext)
-            .map_err(|e| anyhow::anyhow!("Decryption failed: {:?}", e))?;
-
-        Ok(String::from_utf8(plaintext)?)
+        decrypt_with_key(&self.get_or_create_key()?, encrypted)
     }
 
     fn get_or_create_key(&self) -> Result<[u8; 32]> {
@@ -83,3 +112,49 @@ impl TokenEncryption {
         Ok(key)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hmac_sha256_matches_rfc_4231() {
+        let cases: [(&[u8], &[u8], &str); 4] = [
+            (
+                &[0x0b; 20],
+                b"Hi There",
+                "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+            ),
+            (
+                b"Jefe",
+                b"what do ya want for nothing?",
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            ),
+            (
+                &[0xaa; 20],
+                &[0xdd; 50],
+                "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe",
+            ),
+            // A key longer than the block size is hashed first
+            (
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+                "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            ),
+        ];
+
+        for (key, message, expected) in cases {
+            assert_eq!(hex::encode(hmac_sha256(key, message)), expected);
+        }
+    }
+
+    #[test]
+    fn encrypted_data_needs_the_same_key() {
+        let key = derive_key("correct horse", b"salt", 1);
+        let encrypted = encrypt_with_key(&key, "secret").unwrap();
+
+        assert_eq!(decrypt_with_key(&key, &encrypted).unwrap(), "secret");
+        let other = derive_key("battery staple", b"salt", 1);
+        assert!(decrypt_with_key(&other, &encrypted).is_err());
+    }
+}
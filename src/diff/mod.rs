@@ -1,6 +1,9 @@
 use anyhow::Result;
 use regex::Regex;
 use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::LazyLock;
 
 pub fn extract_content(html: &str, css_selector: Option<&str>) -> Result<String> {
     let document = Html::parse_document(html);
@@ -106,44 +109,94 @@ pub fn filter_noise(content: &str) -> String {
     filtered.join("\n")
 }
 
-pub fn compute_diff(old_content: &str, new_content: &str) -> String {
-    let old_lines: Vec<&str> = old_content.lines().collect();
-    let new_lines: Vec<&str> = new_content.lines().collect();
+/// Drops the lines of `content` that match any of `rules`, for noise the
+/// built-in filter does not know about.
+pub fn filter_ignored(content: &str, rules: &[Regex]) -> String {
+    if rules.is_empty() {
+        return content.to_string();
+    }
+
+    let kept: Vec<&str> = content
+        .lines()
+        .filter(|line| !rules.iter().any(|re| re.is_match(line)))
+        .collect();
 
-    let mut added = Vec::new();
-    let mut removed = Vec::new();
+    kept.join("\n")
+}
 
-    for line in new_lines.iter() {
-        if !old_lines.contains(line) {
-            added.push(line.to_string());
-        }
+/// Returns the first number found in `content`, ignoring thousands separators.
+pub fn extract_value(content: &str) -> Option<f64> {
+    static NUMBER: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"-?\d[\d,]*(?:\.\d+)?").expect("Invalid regex pattern - this is a bug")
+    });
+    NUMBER
+        .find(content)
+        .and_then(|m| m.as_str().replace(',', "").parse().ok())
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SiteDiff {
+    pub removed: Vec<String>,
+    pub added: Vec<String>,
+}
+
+impl SiteDiff {
+    pub fn compute(old_content: &str, new_content: &str) -> Self {
+        let old_lines: Vec<&str> = old_content.lines().collect();
+        let new_lines: Vec<&str> = new_content.lines().collect();
+
+        let added = new_lines
+            .iter()
+            .filter(|line| !old_lines.contains(line))
+            .map(|line| line.to_string())
+            .collect();
+
+        let removed = old_lines
+            .iter()
+            .filter(|line| !new_lines.contains(line))
+            .map(|line| line.to_string())
+            .collect();
+
+        Self { removed, added }
     }
 
-    for line in old_lines.iter() {
-        if !new_lines.contains(line) {
-            removed.push(line.to_string());
-        }
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.added.is_empty()
     }
 
-    let mut result = String::new();
+    /// Renders every changed line; `Display` shows at most 10 per side.
+    pub fn to_full_string(&self) -> String {
+        self.render(usize::MAX)
+    }
 
-    if !removed.is_empty() {
-        result.push_str("Removed:\n");
-        for line in removed.iter().take(10) {
-            result.push_str(&format!("- {}\n", line));
+    fn render(&self, limit: usize) -> String {
+        if self.is_empty() {
+            return "Content structure changed (check the site for details)".to_string();
+        }
+
+        let mut result = String::new();
+
+        if !self.removed.is_empty() {
+            result.push_str("Removed:\n");
+            for line in self.removed.iter().take(limit) {
+                result.push_str(&format!("- {}\n", line));
+            }
         }
-    }
 
-    if !added.is_empty() {
-        result.push_str("Added:\n");
-        for line in added.iter().take(10) {
-            result.push_str(&format!("+ {}\n", line));
+        if !self.added.is_empty() {
+            result.push_str("Added:\n");
+            for line in self.added.iter().take(limit) {
+                result.push_str(&format!("+ {}\n", line));
+            }
         }
-    }
 
-    if result.is_empty() {
-        result = "Content structure changed (check the site for details)".to_string();
+        result
     }
+}
 
-    result
+/// Renders a summary of at most 10 removed and 10 added lines.
+impl fmt::Display for SiteDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(10))
+    }
 }
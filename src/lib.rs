@@ -1,14 +1,19 @@
+pub mod backup;
 pub mod cli;
 pub mod config;
 pub mod crypto;
 pub mod diff;
 pub mod monitor;
+pub mod notify;
 pub mod service;
+pub mod state;
 pub mod storage;
 pub mod telegram;
+pub mod transfer;
 pub mod tui;
 
 pub use config::Config;
 pub use monitor::Monitor;
+pub use notify::{Notifier, Notifiers};
 pub use storage::Storage;
 pub use telegram::TelegramClient;
@@ -1,21 +1,22 @@
+mod backup;
 mod cli;
 mod config;
 mod crypto;
 mod diff;
 mod monitor;
+mod notify;
 mod service;
+mod state;
 mod storage;
 mod telegram;
+mod transfer;
 mod tui;
 
 use anyhow::Result;
-use config::Config;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
 
-    let config = Config::new()?;
-
-    cli::run(config).await
+    cli::run().await
 }
@@ -1,29 +1,49 @@
 use crate::config::WatchedSite;
-use crate::diff::{compute_diff, extract_content, filter_noise};
-use crate::storage::Storage;
-use crate::telegram::TelegramClient;
+use crate::diff::{extract_content, extract_value, filter_ignored, filter_noise, SiteDiff};
+use crate::notify::{Notifiers, SiteChange};
+use crate::storage::{CheckRecord, RawResponse, Storage};
 use anyhow::Result;
 use chrono::Utc;
 use sha2::{Digest, Sha256};
-use std::time::Duration;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
-use tracing::{error, info};
+use tracing::{error, info, warn};
+
+/// How often the monitor looks for sites that are due for a check.
+const SCHEDULER_TICK: Duration = Duration::from_secs(30);
 
 pub struct Monitor {
     client: reqwest::Client,
-    telegram: TelegramClient,
-    storage: Storage,
+    notifiers: Notifiers,
+    storage: Box<dyn Storage>,
 }
 
 #[derive(Debug, Clone)]
 pub enum MonitorEvent {
-    SiteChecked { site_id: String, changed: bool },
-    SiteChanged { site_id: String, diff: String },
-    Error { site_id: String, error: String },
+    SiteChecked {
+        site_id: String,
+        changed: bool,
+    },
+    SiteChanged {
+        site_id: String,
+        diff: String,
+    },
+    Error {
+        site_id: String,
+        error: String,
+    },
+    /// A command hook ran; `exit_code` is `None` if it timed out or could not start.
+    HookFinished {
+        site_id: String,
+        hook: String,
+        exit_code: Option<i32>,
+        output: String,
+    },
 }
 
 impl Monitor {
-    pub fn new(telegram: TelegramClient, storage: Storage) -> Self {
+    pub fn new(notifiers: Notifiers, storage: Box<dyn Storage>) -> Self {
         let client = reqwest::Client::builder()
             .user_agent("Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36")
             .timeout(Duration::from_secs(30))
@@ -32,12 +52,12 @@ impl Monitor {
 
         Self {
             client,
-            telegram,
+            notifiers,
             storage,
         }
     }
 
-    pub async fn check_site(&self, site: &mut WatchedSite) -> Result<Option<String>> {
+    pub async fn check_site(&self, site: &mut WatchedSite) -> Result<Option<SiteChange>> {
         info!("Checking site: {} ({})", site.name, site.url);
 
         // Validate URL before making request
@@ -49,6 +69,7 @@ impl Monitor {
             anyhow::bail!("Only HTTP and HTTPS URLs are supported");
         }
 
+        let started = Instant::now();
         let response = self.client.get(&site.url).send().await?;
 
         // Check response size to prevent memory exhaustion
@@ -59,38 +80,187 @@ impl Monitor {
             }
         }
 
+        let status = response.status().as_u16();
+        let headers: Vec<(String, String)> = response
+            .headers()
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.to_string(),
+                    String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                )
+            })
+            .collect();
+
         let html = response.text().await?;
+        let response_ms = started.elapsed().as_millis() as u64;
 
         // Additional size check after download
         if html.len() > 10_000_000 {
             anyhow::bail!("Response too large (>10MB)");
         }
 
-        let content = extract_content(&html, site.css_selector.as_deref())?;
-        let filtered = filter_noise(&content);
+        let filtered = self.extract(site, &html)?;
 
         let hash = self.compute_hash(&filtered);
         site.last_checked = Some(Utc::now());
 
+        let record = CheckRecord {
+            timestamp: Utc::now(),
+            changed: site.last_hash.as_ref().is_some_and(|last| last != &hash),
+            response_ms,
+            value: if site.track_value {
+                extract_value(&filtered)
+            } else {
+                None
+            },
+            hash: Some(hash.clone()),
+            error: None,
+        };
+        if let Err(e) = self.storage.record_check(&site.id, &record) {
+            error!("Failed to record check history for {}: {}", site.id, e);
+        }
+
+        // Every version the site goes through, starting with the baseline
+        if site.archive_raw && site.last_hash.as_ref() != Some(&hash) {
+            let raw = RawResponse {
+                fetched_at: Utc::now(),
+                status,
+                headers,
+                body: html,
+                hash: hash.clone(),
+            };
+            if let Err(e) = self.storage.archive_response(&site.id, &raw) {
+                error!("Failed to archive response of {}: {}", site.id, e);
+            }
+        }
+
         if let Some(ref last_hash) = site.last_hash {
             if &hash != last_hash {
                 let old_content = self.storage.get_snapshot(&site.id)?;
-                let diff = compute_diff(&old_content, &filtered);
+                let diff = SiteDiff::compute(&old_content, &filtered);
 
-                self.storage.save_snapshot(&site.id, &filtered)?;
+                self.storage.save_snapshot(&site.id, &hash, &filtered)?;
+                if let Err(e) = self.storage.save_diff(&site.id, &diff.to_full_string()) {
+                    error!("Failed to save diff for {}: {}", site.id, e);
+                }
                 site.last_hash = Some(hash);
                 site.last_change = Some(Utc::now());
 
-                return Ok(Some(diff));
+                return Ok(Some(SiteChange {
+                    site: site.clone(),
+                    diff,
+                    old_content,
+                    new_content: filtered,
+                    detected_at: Utc::now(),
+                }));
             }
         } else {
-            self.storage.save_snapshot(&site.id, &filtered)?;
+            self.storage.save_snapshot(&site.id, &hash, &filtered)?;
             site.last_hash = Some(hash);
         }
 
         Ok(None)
     }
 
+    /// Re-extracts every archived response of `site` with its current
+    /// selector and noise rules, saving the resulting versions and
+    /// rewriting the check history to match. Returns how many distinct
+    /// versions the archive yields.
+    pub fn reprocess(&self, site: &mut WatchedSite) -> Result<usize> {
+        let archive = self.storage.load_archive(&site.id)?;
+        if archive.is_empty() {
+            anyhow::bail!("No archived responses for {}", site.name);
+        }
+
+        let current = self.storage.get_snapshot(&site.id)?;
+
+        // Hash at fetch time -> (hash now, tracked value now)
+        let mut rehashed = HashMap::new();
+        let mut previous: Option<(String, String)> = None;
+        let mut last_diff = None;
+        let mut versions = 0;
+
+        for raw in &archive {
+            let filtered = match self.extract(site, &raw.body) {
+                Ok(filtered) => filtered,
+                Err(e) => {
+                    warn!(
+                        "Skipping response of {} fetched at {}: {}",
+                        site.name, raw.fetched_at, e
+                    );
+                    continue;
+                }
+            };
+            let hash = self.compute_hash(&filtered);
+            let value = if site.track_value {
+                extract_value(&filtered)
+            } else {
+                None
+            };
+            rehashed.insert(raw.hash.clone(), (hash.clone(), value));
+
+            if previous.as_ref().is_some_and(|(last, _)| last == &hash) {
+                continue;
+            }
+
+            self.storage.save_snapshot(&site.id, &hash, &filtered)?;
+            if let Some((_, old_content)) = &previous {
+                last_diff = Some(SiteDiff::compute(old_content, &filtered));
+            }
+            previous = Some((hash, filtered));
+            versions += 1;
+        }
+
+        let mut history = self.storage.load_history(&site.id)?;
+        let mut last_hash: Option<String> = None;
+        for record in &mut history {
+            let Some(hash) = record.hash.take() else {
+                continue;
+            };
+            let (hash, value) = match rehashed.get(&hash) {
+                Some((new_hash, value)) => (new_hash.clone(), *value),
+                None => (hash, record.value),
+            };
+            record.changed = last_hash.as_ref().is_some_and(|last| last != &hash);
+            record.value = value;
+            last_hash = Some(hash.clone());
+            record.hash = Some(hash);
+        }
+        self.storage.replace_history(&site.id, &history)?;
+
+        let latest = site
+            .last_hash
+            .as_ref()
+            .and_then(|hash| rehashed.get(hash))
+            .map(|(hash, _)| hash.clone());
+
+        match latest {
+            Some(hash) if previous.is_some_and(|(last, _)| last == hash) => {
+                site.last_hash = Some(hash);
+                if let Some(diff) = last_diff {
+                    self.storage.save_diff(&site.id, &diff.to_full_string())?;
+                }
+            }
+            // The archive does not end with the current version, so keep
+            // that as the baseline
+            _ => {
+                if let Some(hash) = &site.last_hash {
+                    self.storage.save_snapshot(&site.id, hash, &current)?;
+                }
+            }
+        }
+
+        Ok(versions)
+    }
+
+    /// The text of `html` that is compared between checks: what the
+    /// selector picks, without noise.
+    fn extract(&self, site: &WatchedSite, html: &str) -> Result<String> {
+        let content = extract_content(html, site.css_selector.as_deref())?;
+        Ok(filter_ignored(&filter_noise(&content), &site.ignore_rules))
+    }
+
     fn compute_hash(&self, content: &str) -> String {
         let mut hasher = Sha256::new();
         hasher.update(content.as_bytes());
@@ -103,7 +273,11 @@ impl Monitor {
         events_tx: mpsc::Sender<MonitorEvent>,
         mut shutdown_rx: tokio::sync::broadcast::Receiver<()>,
     ) {
-        let mut interval = tokio::time::interval(Duration::from_secs(180));
+        let mut tick = tokio::time::interval(SCHEDULER_TICK);
+        // Queued alerts are due on their own schedule, independent of checks
+        let mut flush = tokio::time::interval(Duration::from_secs(60));
+        // When each site is due next; sites not in here are checked right away
+        let mut next_check: HashMap<String, Instant> = HashMap::new();
 
         loop {
             tokio::select! {
@@ -111,50 +285,38 @@ impl Monitor {
                     info!("Monitor shutting down");
                     break;
                 }
-                _ = interval.tick() => {
-                    let sites = self.storage.load_sites();
-                    for mut site in sites {
-                        match self.check_site(&mut site).await {
-                            Ok(Some(diff)) => {
-                                let site_id = site.id.clone();
-                                let _ = self.storage.update_site(&site);
-
-                                let message = format!(
-                                    "🔄 <b>Change detected!</b>\n\n\
-                                    <b>Site:</b> {}\n\
-                                    <b>URL:</b> {}\n\
-                                    <b>Time:</b> {}\n\n\
-                                    <b>Changes:</b>\n{}",
-                                    site.name,
-                                    site.url,
-                                    Utc::now().format("%Y-%m-%d %H:%M:%S UTC"),
-                                    self.format_diff_for_telegram(&diff)
-                                );
-
-                                if let Err(e) = self.telegram.send_message(&message).await {
-                                    error!("Failed to send Telegram notification: {}", e);
-                                }
-
-                                if let Err(e) = events_tx.send(MonitorEvent::SiteChanged {
-                                    site_id,
-                                    diff,
-                                }).await {
-                                    error!("Failed to send event: {}", e);
-                                }
-                            }
-                            Ok(None) => {
-                                let _ = events_tx.send(MonitorEvent::SiteChecked {
-                                    site_id: site.id.clone(),
-                                    changed: false,
-                                }).await;
-                            }
-                            Err(e) => {
-                                let site_id = site.id.clone();
-                                let _ = events_tx.send(MonitorEvent::Error {
-                                    site_id,
-                                    error: e.to_string(),
-                                }).await;
-                            }
+                _ = flush.tick() => self.notifiers.flush_due().await,
+                _ = tick.tick() => {
+                    let Some(config) = self.storage.load_config() else {
+                        continue;
+                    };
+                    for mut site in config.sites.into_iter().filter(|s| s.enabled) {
+                        let now = Instant::now();
+                        if next_check.get(&site.id).is_some_and(|due| *due > now) {
+                            continue;
+                        }
+                        next_check.insert(
+                            site.id.clone(),
+                            now + Duration::from_secs(site.interval_secs(config.check_interval_secs)),
+                        );
+
+                        let event = match self.check_and_notify(&mut site).await {
+                            Ok(Some(change)) => MonitorEvent::SiteChanged {
+                                site_id: site.id.clone(),
+                                diff: change.diff.to_string(),
+                            },
+                            Ok(None) => MonitorEvent::SiteChecked {
+                                site_id: site.id.clone(),
+                                changed: false,
+                            },
+                            Err(e) => MonitorEvent::Error {
+                                site_id: site.id.clone(),
+                                error: e.to_string(),
+                            },
+                        };
+
+                        if let Err(e) = events_tx.send(event).await {
+                            error!("Failed to send event: {}", e);
                         }
                     }
                 }
@@ -162,13 +324,71 @@ impl Monitor {
         }
     }
 
-    fn format_diff_for_telegram(&self, diff: &str) -> String {
-        let lines: Vec<&str> = diff.lines().take(20).collect();
-        let result = lines.join("\n");
-        if diff.lines().count() > 20 {
-            format!("{}\n\n<i>... (truncated)</i>", result)
-        } else {
-            result
+    /// Checks `site`, sends the resulting notifications and persists the
+    /// updated site state.
+    pub async fn check_and_notify(&self, site: &mut WatchedSite) -> Result<Option<SiteChange>> {
+        match self.check_site(site).await {
+            Ok(Some(mut change)) => {
+                self.recover(site).await;
+                self.save_state(site);
+
+                // A snooze, mute or mark as seen may have come in since the
+                // site was loaded
+                if let Some(current) = self
+                    .storage
+                    .load_sites()
+                    .into_iter()
+                    .find(|s| s.id == site.id)
+                {
+                    site.muted = current.muted;
+                    site.snoozed_until = current.snoozed_until;
+                    site.last_seen = current.last_seen;
+                }
+
+                change.site = site.clone();
+                self.notifiers.notify_change(&change).await;
+                Ok(Some(change))
+            }
+            Ok(None) => {
+                self.recover(site).await;
+                self.save_state(site);
+                Ok(None)
+            }
+            Err(e) => {
+                let error = e.to_string();
+
+                let record = CheckRecord {
+                    timestamp: Utc::now(),
+                    changed: false,
+                    response_ms: 0,
+                    value: None,
+                    hash: None,
+                    error: Some(error.clone()),
+                };
+                if let Err(e) = self.storage.record_check(&site.id, &record) {
+                    error!("Failed to record check history for {}: {}", site.id, e);
+                }
+
+                // Only the first failure in a row is reported
+                if site.last_error.is_none() {
+                    self.notifiers.notify_error(site, &error).await;
+                }
+                site.last_error = Some(error);
+                self.save_state(site);
+                Err(e)
+            }
+        }
+    }
+
+    fn save_state(&self, site: &WatchedSite) {
+        if let Err(e) = self.storage.save_state(site) {
+            error!("Failed to save state of {}: {}", site.id, e);
+        }
+    }
+
+    async fn recover(&self, site: &mut WatchedSite) {
+        if site.last_error.take().is_some() {
+            self.notifiers.notify_recovery(site).await;
         }
     }
 }
@@ -0,0 +1,262 @@
+use super::{Notifier, SiteChange};
+use crate::config::WatchedSite;
+use crate::monitor::MonitorEvent;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+/// Runs a local program for every event.
+///
+/// Site metadata is passed in `RNOT_*` environment variables. For changes,
+/// the old and new snapshots and the diff are written to temporary files
+/// (`RNOT_OLD_FILE`, `RNOT_NEW_FILE`, `RNOT_DIFF_FILE`) that are removed
+/// once the program exits. The full event is also written to stdin as JSON.
+///
+/// A digest (`RNOT_EVENT=digest`) covers several sites, so only
+/// `RNOT_DIFF_FILE` is set, holding every diff; stdin lists the changes,
+/// without snapshots.
+pub struct CommandNotifier {
+    name: String,
+    program: String,
+    args: Vec<String>,
+    timeout: Duration,
+    events_tx: Option<mpsc::Sender<MonitorEvent>>,
+}
+
+impl CommandNotifier {
+    pub fn new(
+        name: String,
+        program: String,
+        args: Vec<String>,
+        timeout: Duration,
+        events_tx: Option<mpsc::Sender<MonitorEvent>>,
+    ) -> Self {
+        Self {
+            name,
+            program,
+            args,
+            timeout,
+            events_tx,
+        }
+    }
+
+    /// Runs the program for an event about `sites`; the `RNOT_SITE_*`
+    /// variables are only set for a single site.
+    async fn run(
+        &self,
+        sites: &[&WatchedSite],
+        event: &str,
+        env: Vec<(&str, String)>,
+        stdin: Value,
+    ) -> Result<()> {
+        let mut command = Command::new(&self.program);
+        command
+            .args(&self.args)
+            .env("RNOT_EVENT", event)
+            .env("RNOT_TIMESTAMP", Utc::now().to_rfc3339())
+            .envs(env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        if let [site] = sites {
+            command
+                .env("RNOT_SITE_ID", &site.id)
+                .env("RNOT_SITE_NAME", &site.name)
+                .env("RNOT_SITE_URL", &site.url);
+        }
+        let names = sites
+            .iter()
+            .map(|site| site.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let (exit_code, output) = self
+            .execute(command, stdin.to_string())
+            .await
+            .unwrap_or_else(|e| (None, e.to_string()));
+
+        match exit_code {
+            Some(0) => info!("Hook '{}' for {} exited with 0", self.name, names),
+            Some(code) => warn!(
+                "Hook '{}' for {} exited with {}: {}",
+                self.name, names, code, output
+            ),
+            None => warn!("Hook '{}' for {} failed: {}", self.name, names, output),
+        }
+
+        if let Some(ref events_tx) = self.events_tx {
+            for site in sites {
+                let _ = events_tx
+                    .send(MonitorEvent::HookFinished {
+                        site_id: site.id.clone(),
+                        hook: self.name.clone(),
+                        exit_code,
+                        output: output.clone(),
+                    })
+                    .await;
+            }
+        }
+
+        match exit_code {
+            Some(0) => Ok(()),
+            Some(code) => Err(anyhow::anyhow!("exited with {}: {}", code, output)),
+            None => Err(anyhow::anyhow!(output)),
+        }
+    }
+
+    /// Returns the exit code (`None` if killed by a signal) and the last
+    /// line of output, preferring stderr.
+    async fn execute(&self, mut command: Command, stdin: String) -> Result<(Option<i32>, String)> {
+        let mut child = command
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("Failed to start '{}': {}", self.program, e))?;
+
+        // Feed stdin while waiting, so a program that never reads it cannot
+        // block us past the timeout; write errors just mean it exited early
+        let pipe = child.stdin.take();
+        let feed = async move {
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.write_all(stdin.as_bytes()).await;
+            }
+        };
+        let wait = async { tokio::join!(feed, child.wait_with_output()).1 };
+
+        let output = tokio::time::timeout(self.timeout, wait)
+            .await
+            .map_err(|_| anyhow::anyhow!("Timed out after {}s", self.timeout.as_secs()))??;
+
+        let last_line = |bytes: &[u8]| {
+            String::from_utf8_lossy(bytes)
+                .lines()
+                .rfind(|line| !line.trim().is_empty())
+                .map(|line| line.trim().to_string())
+        };
+        let last_line = last_line(&output.stderr)
+            .or_else(|| last_line(&output.stdout))
+            .unwrap_or_default();
+
+        Ok((output.status.code(), last_line))
+    }
+}
+
+fn site_json(site: &WatchedSite) -> Value {
+    json!({ "id": site.id, "name": site.name, "url": site.url })
+}
+
+#[async_trait]
+impl Notifier for CommandNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn send_change(&self, change: &SiteChange) -> Result<()> {
+        let diff = change.diff.to_string();
+
+        let dir = tempfile::tempdir()?;
+        let old_file = dir.path().join("old.txt");
+        let new_file = dir.path().join("new.txt");
+        let diff_file = dir.path().join("changes.diff");
+        std::fs::write(&old_file, &change.old_content)?;
+        std::fs::write(&new_file, &change.new_content)?;
+        std::fs::write(&diff_file, &diff)?;
+
+        let env = vec![
+            ("RNOT_OLD_FILE", old_file.display().to_string()),
+            ("RNOT_NEW_FILE", new_file.display().to_string()),
+            ("RNOT_DIFF_FILE", diff_file.display().to_string()),
+        ];
+        let stdin = json!({
+            "event": "change",
+            "site": site_json(&change.site),
+            "timestamp": change.detected_at.to_rfc3339(),
+            "diff": {
+                "text": diff,
+                "added": change.diff.added,
+                "removed": change.diff.removed,
+            },
+            "old_content": change.old_content,
+            "new_content": change.new_content,
+        });
+
+        self.run(&[&change.site], "change", env, stdin).await
+    }
+
+    async fn send_digest(&self, changes: &[SiteChange]) -> Result<()> {
+        let mut sites: Vec<&WatchedSite> = Vec::new();
+        for change in changes {
+            if !sites.iter().any(|site| site.id == change.site.id) {
+                sites.push(&change.site);
+            }
+        }
+
+        let diff: Vec<String> = changes
+            .iter()
+            .map(|change| {
+                format!(
+                    "{} ({})\n{}",
+                    change.site.name, change.site.url, change.diff
+                )
+            })
+            .collect();
+        let dir = tempfile::tempdir()?;
+        let diff_file = dir.path().join("changes.diff");
+        std::fs::write(&diff_file, diff.join("\n\n"))?;
+
+        let stdin = json!({
+            "event": "digest",
+            "timestamp": Utc::now().to_rfc3339(),
+            "changes": changes
+                .iter()
+                .map(|change| {
+                    json!({
+                        "site": site_json(&change.site),
+                        "timestamp": change.detected_at.to_rfc3339(),
+                        "diff": {
+                            "text": change.diff.to_string(),
+                            "added": change.diff.added,
+                            "removed": change.diff.removed,
+                        },
+                    })
+                })
+                .collect::<Vec<_>>(),
+        });
+        let env = vec![("RNOT_DIFF_FILE", diff_file.display().to_string())];
+
+        self.run(&sites, "digest", env, stdin).await
+    }
+
+    async fn send_error(&self, site: &WatchedSite, error: &str) -> Result<()> {
+        let stdin = json!({
+            "event": "error",
+            "site": site_json(site),
+            "timestamp": Utc::now().to_rfc3339(),
+            "error": error,
+        });
+
+        self.run(
+            &[site],
+            "error",
+            vec![("RNOT_ERROR", error.to_string())],
+            stdin,
+        )
+        .await
+    }
+
+    async fn send_recovery(&self, site: &WatchedSite) -> Result<()> {
+        let stdin = json!({
+            "event": "recovery",
+            "site": site_json(site),
+            "timestamp": Utc::now().to_rfc3339(),
+        });
+
+        self.run(&[site], "recovery", Vec::new(), stdin).await
+    }
+}
@@ -0,0 +1,183 @@
+use super::{escape_html, truncate_chars, Notifier, SiteChange};
+use crate::config::WatchedSite;
+use anyhow::Result;
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use tracing::warn;
+use zbus::zvariant::Value;
+
+// How long to keep listening for a click on a notification
+const ACTION_TIMEOUT: Duration = Duration::from_secs(3600);
+
+#[zbus::proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+
+    #[zbus(signal)]
+    fn action_invoked(&self, id: u32, action_key: String) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn notification_closed(&self, id: u32, reason: u32) -> zbus::Result<()>;
+}
+
+/// Shows native desktop notifications over the session D-Bus. Bodies are
+/// escaped since most notification servers render a subset of HTML.
+///
+/// The bus is connected on first use, so a daemon without a session bus
+/// (e.g. a system service) only fails when a notification is sent.
+pub struct DesktopNotifier {
+    name: String,
+    connection: OnceCell<zbus::Connection>,
+}
+
+impl DesktopNotifier {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            connection: OnceCell::new(),
+        }
+    }
+
+    /// Shows a notification; clicking it opens `url`, if given.
+    async fn show(&self, summary: &str, body: &str, url: Option<&str>) -> Result<()> {
+        let connection = self
+            .connection
+            .get_or_try_init(zbus::Connection::session)
+            .await?;
+        let proxy = NotificationsProxy::new(connection).await?;
+
+        // Subscribe before sending so a quick click is not missed
+        let mut actions = proxy.receive_action_invoked().await?;
+        let mut closed = proxy.receive_notification_closed().await?;
+
+        let id = proxy
+            .notify(
+                "rnot",
+                0,
+                "",
+                summary,
+                body,
+                if url.is_some() {
+                    &["default", "Open", "open", "Open"]
+                } else {
+                    &[]
+                },
+                HashMap::new(),
+                -1,
+            )
+            .await?;
+
+        let Some(url) = url.map(String::from) else {
+            return Ok(());
+        };
+        tokio::spawn(async move {
+            let wait = async {
+                loop {
+                    tokio::select! {
+                        Some(signal) = actions.next() => {
+                            if signal.args().is_ok_and(|args| args.id == id) {
+                                open_url(&url);
+                                break;
+                            }
+                        }
+                        Some(signal) = closed.next() => {
+                            if signal.args().is_ok_and(|args| args.id == id) {
+                                break;
+                            }
+                        }
+                        else => break,
+                    }
+                }
+            };
+            let _ = tokio::time::timeout(ACTION_TIMEOUT, wait).await;
+        });
+
+        Ok(())
+    }
+}
+
+fn open_url(url: &str) {
+    if let Err(e) = std::process::Command::new("xdg-open").arg(url).spawn() {
+        warn!("Failed to open {}: {}", url, e);
+    }
+}
+
+#[async_trait]
+impl Notifier for DesktopNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn send_change(&self, change: &SiteChange) -> Result<()> {
+        let diff: Vec<String> = change
+            .diff
+            .to_string()
+            .lines()
+            .take(6)
+            .map(String::from)
+            .collect();
+        let body = escape_html(&truncate_chars(&diff.join("\n"), 300));
+
+        self.show(&change.site.name, &body, Some(&change.site.url))
+            .await
+    }
+
+    async fn send_digest(&self, changes: &[SiteChange]) -> Result<()> {
+        let mut names: Vec<&str> = Vec::new();
+        for change in changes {
+            if !names.contains(&change.site.name.as_str()) {
+                names.push(&change.site.name);
+            }
+        }
+
+        let summary = format!("{} change(s)", changes.len());
+        let mut lines: Vec<String> = names.iter().take(6).map(|n| n.to_string()).collect();
+        if names.len() > lines.len() {
+            lines.push(format!("and {} more", names.len() - lines.len()));
+        }
+        let body = escape_html(&truncate_chars(&lines.join("\n"), 300));
+
+        // Only a digest of a single site has one page to open
+        let url = match changes {
+            [first, rest @ ..] if rest.iter().all(|c| c.site.id == first.site.id) => {
+                Some(first.site.url.as_str())
+            }
+            _ => None,
+        };
+        self.show(&summary, &body, url).await
+    }
+
+    async fn send_error(&self, site: &WatchedSite, error: &str) -> Result<()> {
+        let summary = format!("{} - check failing", site.name);
+        self.show(
+            &summary,
+            &escape_html(&truncate_chars(error, 300)),
+            Some(&site.url),
+        )
+        .await
+    }
+
+    async fn send_recovery(&self, site: &WatchedSite) -> Result<()> {
+        let summary = format!("{} - check recovered", site.name);
+        self.show(&summary, &escape_html(&site.url), Some(&site.url))
+            .await
+    }
+}
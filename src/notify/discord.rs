@@ -0,0 +1,140 @@
+use super::{
+    http_client, pack, truncate_chars, Notifier, PartialDigest, SiteChange, DIGEST_DIFF_CHARS,
+};
+use crate::config::WatchedSite;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use reqwest::Client;
+use serde_json::{json, Value};
+
+const COLOR_CHANGE: u32 = 0xF1C40F;
+const COLOR_ERROR: u32 = 0xE74C3C;
+const COLOR_RECOVERY: u32 = 0x2ECC71;
+
+// Embed descriptions are capped at 4096 characters
+const MAX_DESCRIPTION_CHARS: usize = 4096;
+
+/// Posts embeds to a Discord channel webhook.
+pub struct DiscordNotifier {
+    client: Client,
+    name: String,
+    webhook_url: String,
+}
+
+impl DiscordNotifier {
+    pub fn new(name: String, webhook_url: String) -> Self {
+        Self {
+            client: http_client(),
+            name,
+            webhook_url,
+        }
+    }
+
+    async fn post(&self, embed: Value) -> Result<()> {
+        let payload = json!({ "embeds": [embed] });
+        let response = self
+            .client
+            .post(&self.webhook_url)
+            .json(&payload)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error = response.text().await.unwrap_or_default();
+            return Err(anyhow::anyhow!("Discord returned {}: {}", status, error));
+        }
+
+        Ok(())
+    }
+}
+
+fn embed(title: String, site: &WatchedSite, color: u32, description: String) -> Value {
+    json!({
+        "title": truncate_chars(&title, 256),
+        "url": site.url,
+        "color": color,
+        "description": description,
+        "timestamp": Utc::now().to_rfc3339(),
+    })
+}
+
+#[async_trait]
+impl Notifier for DiscordNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn send_change(&self, change: &SiteChange) -> Result<()> {
+        // A `diff` code block colours the "- " and "+ " lines
+        let diff = change.diff.to_string().replace("```", "'''");
+        let description = format!(
+            "```diff\n{}\n```",
+            truncate_chars(&diff, MAX_DESCRIPTION_CHARS - 12)
+        );
+
+        self.post(embed(
+            format!("🔄 Change detected: {}", change.site.name),
+            &change.site,
+            COLOR_CHANGE,
+            description,
+        ))
+        .await
+    }
+
+    async fn send_digest(&self, changes: &[SiteChange]) -> Result<()> {
+        let entries = changes
+            .iter()
+            .map(|change| {
+                let diff = change.diff.to_string().replace("```", "'''");
+                format!(
+                    "**[{}]({})** ({})\n```diff\n{}\n```\n",
+                    change.site.name.replace(['[', ']'], ""),
+                    change.site.url,
+                    change.detected_at.format("%Y-%m-%d %H:%M UTC"),
+                    truncate_chars(&diff, DIGEST_DIFF_CHARS)
+                )
+            })
+            .collect();
+        let mut sent = 0;
+
+        for run in pack(entries, MAX_DESCRIPTION_CHARS, |entry| {
+            entry.chars().count()
+        }) {
+            let embed = json!({
+                "title": format!("📬 {} change(s)", changes.len()),
+                "color": COLOR_CHANGE,
+                "description": run.concat(),
+                "timestamp": Utc::now().to_rfc3339(),
+            });
+
+            self.post(embed)
+                .await
+                .map_err(|error| PartialDigest { sent, error })?;
+            sent += run.len();
+        }
+
+        Ok(())
+    }
+
+    async fn send_error(&self, site: &WatchedSite, error: &str) -> Result<()> {
+        self.post(embed(
+            format!("⚠️ Check failing: {}", site.name),
+            site,
+            COLOR_ERROR,
+            truncate_chars(error, MAX_DESCRIPTION_CHARS),
+        ))
+        .await
+    }
+
+    async fn send_recovery(&self, site: &WatchedSite) -> Result<()> {
+        self.post(embed(
+            format!("✅ Check recovered: {}", site.name),
+            site,
+            COLOR_RECOVERY,
+            site.url.clone(),
+        ))
+        .await
+    }
+}
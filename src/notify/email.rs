@@ -0,0 +1,300 @@
+use super::{escape_html, Notifier, SiteChange};
+use crate::config::{SmtpSecurity, WatchedSite};
+use crate::diff::SiteDiff;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+/// Sends multipart (plain text + HTML) emails over SMTP.
+pub struct EmailNotifier {
+    name: String,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl EmailNotifier {
+    pub fn new(
+        name: String,
+        host: &str,
+        port: Option<u16>,
+        security: SmtpSecurity,
+        credentials: Option<(String, String)>,
+        from: &str,
+        to: &[String],
+    ) -> Result<Self> {
+        let mut builder = match security {
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+            SmtpSecurity::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+            // Plain connections are meant for local relays and SMTP stand-ins
+            SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+        };
+
+        if let Some(port) = port {
+            builder = builder.port(port);
+        }
+
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        if to.is_empty() {
+            anyhow::bail!("At least one recipient is required");
+        }
+
+        let from = from
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid sender '{}': {}", from, e))?;
+        let to = to
+            .iter()
+            .map(|addr| {
+                addr.parse()
+                    .map_err(|e| anyhow::anyhow!("Invalid recipient '{}': {}", addr, e))
+            })
+            .collect::<Result<Vec<Mailbox>>>()?;
+
+        Ok(Self {
+            name,
+            transport: builder.build(),
+            from,
+            to,
+        })
+    }
+
+    async fn send(&self, subject: &str, text: String, html: String) -> Result<()> {
+        let mut builder = Message::builder().from(self.from.clone()).subject(subject);
+        for recipient in &self.to {
+            builder = builder.to(recipient.clone());
+        }
+
+        let email = builder.multipart(MultiPart::alternative_plain_html(text, html))?;
+        self.transport.send(email).await?;
+
+        Ok(())
+    }
+}
+
+fn html_document(title: &str, site: &WatchedSite, body: &str) -> String {
+    format!(
+        "<html><body>\
+        <h2>{}</h2>\
+        <p><b>Site:</b> {}<br><b>URL:</b> <a href=\"{url}\">{url}</a><br><b>Time:</b> {}</p>\
+        {}\
+        </body></html>",
+        escape_html(title),
+        escape_html(&site.name),
+        Utc::now().format("%Y-%m-%d %H:%M:%S UTC"),
+        body,
+        url = escape_html(&site.url),
+    )
+}
+
+fn diff_html(diff: &SiteDiff) -> String {
+    let mut rows = String::new();
+    for line in &diff.removed {
+        rows.push_str(&format!(
+            "<tr style=\"background:#ffeef0\"><td>-</td><td>{}</td></tr>",
+            escape_html(line)
+        ));
+    }
+    for line in &diff.added {
+        rows.push_str(&format!(
+            "<tr style=\"background:#e6ffed\"><td>+</td><td>{}</td></tr>",
+            escape_html(line)
+        ));
+    }
+
+    if rows.is_empty() {
+        "<p>Content structure changed (check the site for details)</p>".to_string()
+    } else {
+        format!(
+            "<table style=\"font-family:monospace;border-collapse:collapse\">{}</table>",
+            rows
+        )
+    }
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn send_change(&self, change: &SiteChange) -> Result<()> {
+        let site = &change.site;
+        let time = change.detected_at.format("%Y-%m-%d %H:%M:%S UTC");
+
+        let text = format!(
+            "Change detected!\n\nSite: {}\nURL: {}\nTime: {}\n\nChanges:\n{}",
+            site.name, site.url, time, change.diff
+        );
+
+        let body = diff_html(&change.diff);
+
+        let subject = format!("[RNOT] Change detected: {}", site.name);
+        self.send(
+            &subject,
+            text,
+            html_document("Change detected", site, &body),
+        )
+        .await
+    }
+
+    async fn send_digest(&self, changes: &[SiteChange]) -> Result<()> {
+        let title = format!("{} change(s)", changes.len());
+        let mut text = format!("{}\n", title);
+        let mut html = format!("<html><body><h2>{}</h2>", escape_html(&title));
+
+        for change in changes {
+            let site = &change.site;
+            let time = change.detected_at.format("%Y-%m-%d %H:%M:%S UTC");
+            text.push_str(&format!(
+                "\nSite: {}\nURL: {}\nTime: {}\n\nChanges:\n{}\n",
+                site.name, site.url, time, change.diff
+            ));
+            html.push_str(&format!(
+                "<h3><a href=\"{}\">{}</a></h3><p><b>Time:</b> {}</p>{}",
+                escape_html(&site.url),
+                escape_html(&site.name),
+                time,
+                diff_html(&change.diff)
+            ));
+        }
+        html.push_str("</body></html>");
+
+        self.send(&format!("[RNOT] {}", title), text, html).await
+    }
+
+    async fn send_error(&self, site: &WatchedSite, error: &str) -> Result<()> {
+        let text = format!(
+            "Check failing\n\nSite: {}\nURL: {}\n\nError: {}",
+            site.name, site.url, error
+        );
+        let body = format!("<p><b>Error:</b> {}</p>", escape_html(error));
+
+        let subject = format!("[RNOT] Check failing: {}", site.name);
+        self.send(&subject, text, html_document("Check failing", site, &body))
+            .await
+    }
+
+    async fn send_recovery(&self, site: &WatchedSite) -> Result<()> {
+        let text = format!("Check recovered\n\nSite: {}\nURL: {}", site.name, site.url);
+
+        let subject = format!("[RNOT] Check recovered: {}", site.name);
+        self.send(&subject, text, html_document("Check recovered", site, ""))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// Accepts one SMTP session on `listener` and returns the envelope
+    /// commands and the message it was given.
+    async fn smtp_stand_in(listener: TcpListener) -> (Vec<String>, String) {
+        let (socket, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = socket.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let (mut commands, mut message) = (Vec::new(), String::new());
+
+        writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            let reply: &[u8] = match line.split(' ').next().unwrap() {
+                "EHLO" => b"250 localhost\r\n",
+                "DATA" => {
+                    writer.write_all(b"354 End with .\r\n").await.unwrap();
+                    while let Some(line) = lines.next_line().await.unwrap() {
+                        if line == "." {
+                            break;
+                        }
+                        message.push_str(&line);
+                        message.push('\n');
+                    }
+                    b"250 Queued\r\n"
+                }
+                "QUIT" => {
+                    writer.write_all(b"221 Bye\r\n").await.unwrap();
+                    break;
+                }
+                _ => {
+                    commands.push(line);
+                    b"250 OK\r\n"
+                }
+            };
+            writer.write_all(reply).await.unwrap();
+        }
+
+        (commands, message)
+    }
+
+    #[tokio::test]
+    async fn changes_are_mailed_to_every_recipient() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(smtp_stand_in(listener));
+
+        let notifier = EmailNotifier::new(
+            "mail".to_string(),
+            "127.0.0.1",
+            Some(port),
+            SmtpSecurity::None,
+            None,
+            "rnot <rnot@example.com>",
+            &["a@example.com".to_string(), "b@example.com".to_string()],
+        )
+        .unwrap();
+        let change = SiteChange {
+            site: WatchedSite::new(
+                "https://example.com".to_string(),
+                "Shop & Co".to_string(),
+                None,
+            ),
+            diff: SiteDiff::compute("Price 5", "Price 4"),
+            old_content: String::new(),
+            new_content: String::new(),
+            detected_at: Utc::now(),
+        };
+
+        notifier.send_change(&change).await.unwrap();
+        let (commands, message) = server.await.unwrap();
+
+        assert_eq!(
+            commands,
+            [
+                "MAIL FROM:<rnot@example.com>",
+                "RCPT TO:<a@example.com>",
+                "RCPT TO:<b@example.com>",
+            ]
+        );
+        assert!(message.contains("Subject: [RNOT] Change detected: Shop & Co"));
+        assert!(message.contains("Content-Type: multipart/alternative"));
+        assert!(message.contains("+ Price 4"));
+        assert!(message.contains("<td>+</td><td>Price 4</td>"));
+        assert!(message.contains("Shop &amp; Co"));
+    }
+
+    #[test]
+    fn notifiers_need_a_valid_sender_and_a_recipient() {
+        let new = |from: &str, to: &[String]| {
+            EmailNotifier::new(
+                "mail".to_string(),
+                "localhost",
+                None,
+                SmtpSecurity::None,
+                None,
+                from,
+                to,
+            )
+        };
+
+        assert!(new("rnot@example.com", &[]).is_err());
+        assert!(new("not an address", &["a@example.com".to_string()]).is_err());
+        assert!(new("rnot@example.com", &["nope".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,182 @@
+use super::{
+    escape_html, http_client, pack, truncate_chars, Notifier, PartialDigest, SiteChange,
+    DIGEST_DIFF_CHARS,
+};
+use crate::config::WatchedSite;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use reqwest::Client;
+use serde_json::json;
+
+// Events are capped at 64 KiB; leaves room for the plain-text body
+const MAX_DIGEST_CHARS: usize = 24_000;
+
+/// Sends `m.room.message` events through the Matrix client-server API.
+pub struct MatrixNotifier {
+    client: Client,
+    name: String,
+    homeserver: url::Url,
+    room_id: String,
+    access_token: String,
+}
+
+impl MatrixNotifier {
+    pub fn new(
+        name: String,
+        homeserver: &str,
+        room_id: String,
+        access_token: String,
+    ) -> Result<Self> {
+        let homeserver = url::Url::parse(homeserver)
+            .map_err(|e| anyhow::anyhow!("Invalid homeserver URL: {}", e))?;
+        if homeserver.cannot_be_a_base() {
+            anyhow::bail!("Invalid homeserver URL: {}", homeserver);
+        }
+
+        Ok(Self {
+            client: http_client(),
+            name,
+            homeserver,
+            room_id,
+            access_token,
+        })
+    }
+
+    async fn send(&self, body: String, formatted_body: String) -> Result<()> {
+        let txn_id = format!(
+            "rnot-{}-{}",
+            Utc::now().timestamp_millis(),
+            rand::random::<u32>()
+        );
+
+        let mut url = self.homeserver.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Invalid homeserver URL"))?
+            .pop_if_empty()
+            .extend([
+                "_matrix",
+                "client",
+                "v3",
+                "rooms",
+                &self.room_id,
+                "send",
+                "m.room.message",
+                &txn_id,
+            ]);
+
+        let payload = json!({
+            "msgtype": "m.text",
+            "body": body,
+            "format": "org.matrix.custom.html",
+            "formatted_body": formatted_body,
+        });
+
+        let response = self
+            .client
+            .put(url)
+            .bearer_auth(&self.access_token)
+            .json(&payload)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error = response.text().await.unwrap_or_default();
+            return Err(anyhow::anyhow!("Matrix returned {}: {}", status, error));
+        }
+
+        Ok(())
+    }
+}
+
+fn site_html(title: &str, site: &WatchedSite) -> String {
+    format!(
+        "<h4>{}</h4><p><b>Site:</b> <a href=\"{}\">{}</a><br><b>Time:</b> {}</p>",
+        title,
+        escape_html(&site.url),
+        escape_html(&site.name),
+        Utc::now().format("%Y-%m-%d %H:%M:%S UTC")
+    )
+}
+
+#[async_trait]
+impl Notifier for MatrixNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn send_change(&self, change: &SiteChange) -> Result<()> {
+        let site = &change.site;
+        let diff = change.diff.to_string();
+
+        let body = format!(
+            "🔄 Change detected: {}\n{}\n\n{}",
+            site.name, site.url, diff
+        );
+        let formatted_body = format!(
+            "{}<pre><code class=\"language-diff\">{}</code></pre>",
+            site_html("🔄 Change detected", site),
+            escape_html(&diff)
+        );
+
+        self.send(body, formatted_body).await
+    }
+
+    async fn send_digest(&self, changes: &[SiteChange]) -> Result<()> {
+        let title = format!("📬 {} change(s)", changes.len());
+        let entries = changes
+            .iter()
+            .map(|change| {
+                let site = &change.site;
+                let time = change.detected_at.format("%Y-%m-%d %H:%M UTC");
+                let diff = truncate_chars(&change.diff.to_string(), DIGEST_DIFF_CHARS);
+                let body = format!("\n\n🔄 {} ({})\n{}\n{}", site.name, time, site.url, diff);
+                let html = format!(
+                    "<p><b><a href=\"{}\">{}</a></b> ({})</p>\
+                    <pre><code class=\"language-diff\">{}</code></pre>",
+                    escape_html(&site.url),
+                    escape_html(&site.name),
+                    time,
+                    escape_html(&diff)
+                );
+                (body, html)
+            })
+            .collect();
+        let mut sent = 0;
+
+        for run in pack(entries, MAX_DIGEST_CHARS, |(_, html)| html.len()) {
+            let count = run.len();
+            let (bodies, htmls): (Vec<String>, Vec<String>) = run.into_iter().unzip();
+            let body = format!("{}{}", title, bodies.concat());
+            let formatted_body = format!("<h4>{}</h4>{}", title, htmls.concat());
+
+            self.send(body, formatted_body)
+                .await
+                .map_err(|error| PartialDigest { sent, error })?;
+            sent += count;
+        }
+
+        Ok(())
+    }
+
+    async fn send_error(&self, site: &WatchedSite, error: &str) -> Result<()> {
+        let body = format!(
+            "⚠️ Check failing: {}\n{}\n\nError: {}",
+            site.name, site.url, error
+        );
+        let formatted_body = format!(
+            "{}<p><b>Error:</b> {}</p>",
+            site_html("⚠️ Check failing", site),
+            escape_html(error)
+        );
+
+        self.send(body, formatted_body).await
+    }
+
+    async fn send_recovery(&self, site: &WatchedSite) -> Result<()> {
+        let body = format!("✅ Check recovered: {}\n{}", site.name, site.url);
+
+        self.send(body, site_html("✅ Check recovered", site)).await
+    }
+}
@@ -0,0 +1,792 @@
+mod command;
+#[cfg(target_os = "linux")]
+mod desktop;
+mod discord;
+mod email;
+mod matrix;
+mod schedule;
+mod slack;
+mod webhook;
+
+use crate::config::{Config, DeliveryMode, NotifierConfig, WatchedSite};
+use crate::diff::SiteDiff;
+use crate::monitor::MonitorEvent;
+use crate::storage::{self, Storage};
+use crate::telegram::TelegramClient;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use schedule::{is_due, parse_time, QuietWindow};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+pub use command::CommandNotifier;
+#[cfg(target_os = "linux")]
+pub use desktop::DesktopNotifier;
+pub use discord::DiscordNotifier;
+pub use email::EmailNotifier;
+pub use matrix::MatrixNotifier;
+pub use slack::SlackNotifier;
+pub use webhook::WebhookNotifier;
+
+fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .expect("Failed to build HTTP client")
+}
+
+/// Cuts `text` to at most `max` characters, marking the cut with an ellipsis.
+pub fn truncate_chars(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(max.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Escapes text for inclusion in HTML markup.
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SiteChange {
+    pub site: WatchedSite,
+    pub diff: SiteDiff,
+    pub old_content: String,
+    pub new_content: String,
+    pub detected_at: DateTime<Utc>,
+}
+
+impl SiteChange {
+    /// The change without the snapshots it was computed from, which a queue
+    /// has no use for; digests only show diffs.
+    fn without_contents(&self) -> Self {
+        Self {
+            site: self.site.clone(),
+            diff: self.diff.clone(),
+            old_content: String::new(),
+            new_content: String::new(),
+            detected_at: self.detected_at,
+        }
+    }
+}
+
+/// How much of each diff a digest shows.
+pub const DIGEST_DIFF_CHARS: usize = 500;
+
+/// Returned by [`Notifier::send_digest`] when it fails after delivering the
+/// first `sent` changes, so that those are not sent again.
+#[derive(Debug, thiserror::Error)]
+#[error("{error} ({sent} change(s) were sent before)")]
+pub struct PartialDigest {
+    pub sent: usize,
+    pub error: anyhow::Error,
+}
+
+/// Groups `items` into runs whose `len` adds up to at most `max`, for
+/// digests spread over several size-capped messages. Items are kept whole.
+pub fn pack<T>(items: Vec<T>, max: usize, len: impl Fn(&T) -> usize) -> Vec<Vec<T>> {
+    let mut runs: Vec<Vec<T>> = Vec::new();
+    let mut current = Vec::new();
+    let mut current_len = 0;
+
+    for item in items {
+        let item_len = len(&item);
+        if !current.is_empty() && current_len + item_len > max {
+            runs.push(std::mem::take(&mut current));
+            current_len = 0;
+        }
+        current_len += item_len;
+        current.push(item);
+    }
+
+    if !current.is_empty() {
+        runs.push(current);
+    }
+    runs
+}
+
+/// An alert waiting in the queue of a notifier. Changes wait for their
+/// batch or digest, errors and recoveries only for quiet hours to end.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Pending {
+    Change(SiteChange),
+    Error { site: WatchedSite, error: String },
+    Recovery { site: WatchedSite },
+}
+
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn send_change(&self, change: &SiteChange) -> Result<()>;
+
+    async fn send_error(&self, site: &WatchedSite, error: &str) -> Result<()>;
+
+    async fn send_recovery(&self, site: &WatchedSite) -> Result<()>;
+
+    /// Sends changes collected by a batched or digest delivery mode, which
+    /// come without their snapshots. By default each change is sent on its
+    /// own. Failing partway returns a [`PartialDigest`].
+    async fn send_digest(&self, changes: &[SiteChange]) -> Result<()> {
+        for (sent, change) in changes.iter().enumerate() {
+            self.send_change(change)
+                .await
+                .map_err(|error| PartialDigest { sent, error })?;
+        }
+        Ok(())
+    }
+}
+
+/// The configured notifiers, routing each site to the subset it selects.
+/// Notifiers with a batched or digest delivery mode queue change alerts in
+/// storage until [`Notifiers::flush_due`] sends them; notifiers in quiet
+/// hours queue every alert.
+pub struct Notifiers {
+    notifiers: Vec<Box<dyn Notifier>>,
+    delivery: BTreeMap<String, DeliveryMode>,
+    quiet: BTreeMap<String, QuietWindow>,
+    queue: Option<Box<dyn Storage>>,
+}
+
+impl Notifiers {
+    /// Notifiers that all deliver immediately.
+    pub fn new(notifiers: Vec<Box<dyn Notifier>>) -> Self {
+        Self {
+            notifiers,
+            delivery: BTreeMap::new(),
+            quiet: BTreeMap::new(),
+            queue: None,
+        }
+    }
+
+    /// Builds the notifiers configured in `config`. Command hooks report
+    /// their exit status on `events_tx` when given.
+    pub fn from_config(config: &mut Config, events_tx: Option<mpsc::Sender<MonitorEvent>>) -> Self {
+        let token = config.get_telegram_token();
+        let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+
+        for notifier in &config.app_config.notifiers {
+            match notifier {
+                NotifierConfig::Telegram {
+                    name,
+                    chat_id,
+                    message_thread_id,
+                } => {
+                    if token.is_none() {
+                        warn!("Notifier '{}' skipped: no Telegram token set", name);
+                        continue;
+                    }
+                    notifiers.push(Box::new(
+                        TelegramClient::new(token.clone(), Some(chat_id.clone()))
+                            .with_name(name)
+                            .with_thread(*message_thread_id),
+                    ));
+                }
+                NotifierConfig::Webhook {
+                    name,
+                    url,
+                    headers,
+                    template,
+                    sign,
+                } => {
+                    let secret = if *sign {
+                        match config.get_secret(name) {
+                            Some(secret) => Some(secret),
+                            None => {
+                                warn!(
+                                    "Notifier '{}' skipped: signing enabled but no secret set",
+                                    name
+                                );
+                                continue;
+                            }
+                        }
+                    } else {
+                        None
+                    };
+                    notifiers.push(Box::new(WebhookNotifier::new(
+                        name.clone(),
+                        url.clone(),
+                        headers.clone(),
+                        template.clone(),
+                        secret,
+                    )));
+                }
+                NotifierConfig::Email {
+                    name,
+                    host,
+                    port,
+                    security,
+                    username,
+                    from,
+                    to,
+                } => {
+                    let credentials = match username {
+                        Some(username) => match config.get_secret(name) {
+                            Some(password) => Some((username.clone(), password)),
+                            None => {
+                                warn!("Notifier '{}' skipped: no SMTP password set", name);
+                                continue;
+                            }
+                        },
+                        None => None,
+                    };
+                    match EmailNotifier::new(
+                        name.clone(),
+                        host,
+                        *port,
+                        *security,
+                        credentials,
+                        from,
+                        to,
+                    ) {
+                        Ok(notifier) => notifiers.push(Box::new(notifier)),
+                        Err(e) => warn!("Notifier '{}' skipped: {}", name, e),
+                    }
+                }
+                NotifierConfig::Slack { name } => match config.get_secret(name) {
+                    Some(webhook_url) => {
+                        notifiers.push(Box::new(SlackNotifier::new(name.clone(), webhook_url)))
+                    }
+                    None => warn!("Notifier '{}' skipped: no webhook URL set", name),
+                },
+                NotifierConfig::Discord { name } => match config.get_secret(name) {
+                    Some(webhook_url) => {
+                        notifiers.push(Box::new(DiscordNotifier::new(name.clone(), webhook_url)))
+                    }
+                    None => warn!("Notifier '{}' skipped: no webhook URL set", name),
+                },
+                NotifierConfig::Matrix {
+                    name,
+                    homeserver,
+                    room_id,
+                } => {
+                    let access_token = match config.get_secret(name) {
+                        Some(token) => token,
+                        None => {
+                            warn!("Notifier '{}' skipped: no access token set", name);
+                            continue;
+                        }
+                    };
+                    match MatrixNotifier::new(
+                        name.clone(),
+                        homeserver,
+                        room_id.clone(),
+                        access_token,
+                    ) {
+                        Ok(notifier) => notifiers.push(Box::new(notifier)),
+                        Err(e) => warn!("Notifier '{}' skipped: {}", name, e),
+                    }
+                }
+                NotifierConfig::Desktop { name } => {
+                    #[cfg(target_os = "linux")]
+                    notifiers.push(Box::new(DesktopNotifier::new(name.clone())));
+                    #[cfg(not(target_os = "linux"))]
+                    warn!(
+                        "Notifier '{}' skipped: desktop notifications need Linux",
+                        name
+                    );
+                }
+                NotifierConfig::Command {
+                    name,
+                    program,
+                    args,
+                    timeout_secs,
+                } => notifiers.push(Box::new(CommandNotifier::new(
+                    name.clone(),
+                    program.clone(),
+                    args.clone(),
+                    Duration::from_secs(*timeout_secs),
+                    events_tx.clone(),
+                ))),
+            }
+        }
+
+        // `telegram_chat_id` is the destination named "telegram", unless a
+        // notifier claims that name
+        let has_default = config
+            .app_config
+            .notifiers
+            .iter()
+            .any(|n| n.name() == "telegram");
+        if !has_default && token.is_some() {
+            if let Some(ref chat_id) = config.app_config.telegram_chat_id {
+                notifiers.push(Box::new(TelegramClient::new(token, Some(chat_id.clone()))));
+            }
+        }
+
+        for site in &config.app_config.sites {
+            for name in site.notifiers.iter().flatten() {
+                if !notifiers.iter().any(|n| n.name() == name) {
+                    warn!("Site '{}' routes to unknown notifier '{}'", site.name, name);
+                }
+            }
+        }
+        for (tag, group) in &config.app_config.groups {
+            for name in group.notifiers.iter().flatten() {
+                if !notifiers.iter().any(|n| n.name() == name) {
+                    warn!("Group '{}' routes to unknown notifier '{}'", tag, name);
+                }
+            }
+        }
+
+        let mut delivery = BTreeMap::new();
+        for (name, mode) in &config.app_config.delivery {
+            if !notifiers.iter().any(|n| n.name() == name) {
+                warn!("Delivery mode set for unknown notifier '{}'", name);
+                continue;
+            }
+            if let DeliveryMode::Digest { at } = mode {
+                if let Err(e) = parse_time(at) {
+                    warn!("Notifier '{}' sends immediately: {}", name, e);
+                    continue;
+                }
+            }
+            delivery.insert(name.clone(), mode.clone());
+        }
+
+        let mut quiet = BTreeMap::new();
+        for (name, hours) in &config.app_config.quiet_hours {
+            if !notifiers.iter().any(|n| n.name() == name) {
+                warn!("Quiet hours set for unknown notifier '{}'", name);
+                continue;
+            }
+            match QuietWindow::parse(hours) {
+                Ok(window) => {
+                    quiet.insert(name.clone(), window);
+                }
+                Err(e) => warn!("Quiet hours for '{}' ignored: {}", name, e),
+            }
+        }
+
+        let queue = match storage::open(config) {
+            Ok(storage) => Some(storage),
+            Err(e) => {
+                warn!("Batching and quiet hours disabled: {}", e);
+                None
+            }
+        };
+
+        Self {
+            notifiers,
+            delivery,
+            quiet,
+            queue,
+        }
+    }
+
+    fn delivery(&self, name: &str) -> &DeliveryMode {
+        static IMMEDIATE: DeliveryMode = DeliveryMode::Immediate;
+        self.delivery.get(name).unwrap_or(&IMMEDIATE)
+    }
+
+    fn is_quiet(&self, name: &str, now: DateTime<Utc>) -> bool {
+        self.quiet
+            .get(name)
+            .is_some_and(|window| window.contains(now))
+    }
+
+    /// Whether alerts for `site` are held back from `notifier` right now.
+    fn in_quiet_hours(&self, notifier: &dyn Notifier, site: &WatchedSite) -> bool {
+        if site.urgent || !self.is_quiet(notifier.name(), Utc::now()) {
+            return false;
+        }
+        info!(
+            "Alert for {} held back: '{}' is in quiet hours",
+            site.name,
+            notifier.name()
+        );
+        true
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.notifiers.is_empty()
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.notifiers.iter().map(|n| n.name()).collect()
+    }
+
+    fn routed<'a>(&'a self, site: &'a WatchedSite) -> impl Iterator<Item = &'a dyn Notifier> {
+        self.notifiers
+            .iter()
+            .map(|n| n.as_ref())
+            .filter(move |n| site.routes_to(n.name()))
+    }
+
+    pub async fn notify_change(&self, change: &SiteChange) {
+        if change.site.is_silenced() {
+            info!("Change alert for {} suppressed (muted)", change.site.name);
+            return;
+        }
+
+        for notifier in self.routed(&change.site) {
+            if let Some(ref queue) = self.queue {
+                let held = *self.delivery(notifier.name()) != DeliveryMode::Immediate;
+                if held || self.in_quiet_hours(notifier, &change.site) {
+                    let alert = Pending::Change(change.without_contents());
+                    match queue.queue_pending(notifier.name(), &alert) {
+                        Ok(()) => continue,
+                        Err(e) => error!(
+                            "Failed to queue change for '{}', sending now: {}",
+                            notifier.name(),
+                            e
+                        ),
+                    }
+                }
+            }
+
+            if let Err(e) = notifier.send_change(change).await {
+                error!("Failed to send change via '{}': {}", notifier.name(), e);
+            }
+        }
+    }
+
+    /// Sends the queued alerts of every notifier that is out of quiet
+    /// hours, in the order they were queued: errors and recoveries at once,
+    /// changes once their batch window or digest time has come, with each
+    /// run of consecutive changes as one digest. Once a send fails, it and
+    /// every alert after it stay queued.
+    pub async fn flush_due(&self) {
+        let Some(ref queue) = self.queue else {
+            return;
+        };
+
+        let now = Utc::now();
+        for notifier in &self.notifiers {
+            let mode = self.delivery(notifier.name());
+            let has_quiet_hours = self.quiet.contains_key(notifier.name());
+            if (*mode == DeliveryMode::Immediate && !has_quiet_hours)
+                || self.is_quiet(notifier.name(), now)
+            {
+                continue;
+            }
+
+            let pending = match queue.load_pending(notifier.name()) {
+                Ok(pending) => pending,
+                Err(e) => {
+                    error!("Failed to load queue of '{}': {}", notifier.name(), e);
+                    continue;
+                }
+            };
+            if pending.is_empty() {
+                continue;
+            }
+
+            let changes_due = pending
+                .iter()
+                .filter_map(|item| match item {
+                    Pending::Change(change) => Some(change.detected_at),
+                    _ => None,
+                })
+                .min()
+                .is_some_and(|first| is_due(mode, first, now));
+
+            let mut unsent = Vec::new();
+            let mut failed = false;
+            let runs =
+                pending.chunk_by(|a, b| matches!((a, b), (Pending::Change(_), Pending::Change(_))));
+            for run in runs {
+                if failed {
+                    unsent.extend_from_slice(run);
+                    continue;
+                }
+                let result = match &run[0] {
+                    Pending::Change(_) if !changes_due => {
+                        unsent.extend_from_slice(run);
+                        continue;
+                    }
+                    Pending::Change(_) => {
+                        let changes: Vec<SiteChange> = run
+                            .iter()
+                            .filter_map(|item| match item {
+                                Pending::Change(change) => Some(change.clone()),
+                                _ => None,
+                            })
+                            .collect();
+                        info!(
+                            "Sending {} queued change(s) via '{}'",
+                            changes.len(),
+                            notifier.name()
+                        );
+                        notifier.send_digest(&changes).await
+                    }
+                    Pending::Error { site, error } => notifier.send_error(site, error).await,
+                    Pending::Recovery { site } => notifier.send_recovery(site).await,
+                };
+                if let Err(e) = result {
+                    error!(
+                        "Failed to send queued alert via '{}': {}",
+                        notifier.name(),
+                        e
+                    );
+                    let sent = e.downcast_ref::<PartialDigest>().map_or(0, |p| p.sent);
+                    unsent.extend_from_slice(&run[sent.min(run.len())..]);
+                    failed = true;
+                }
+            }
+
+            if unsent.len() == pending.len() {
+                continue;
+            }
+            if let Err(e) = queue.replace_pending(notifier.name(), pending.len(), &unsent) {
+                error!("Failed to update queue of '{}': {}", notifier.name(), e);
+            }
+        }
+    }
+
+    /// Queues `alert` for `notifier` if it is in quiet hours for `site`.
+    /// Returns false if the alert should be sent now.
+    fn hold(&self, notifier: &dyn Notifier, site: &WatchedSite, alert: Pending) -> bool {
+        let Some(ref queue) = self.queue else {
+            return false;
+        };
+        if !self.in_quiet_hours(notifier, site) {
+            return false;
+        }
+        match queue.queue_pending(notifier.name(), &alert) {
+            Ok(()) => true,
+            Err(e) => {
+                error!(
+                    "Failed to queue alert for '{}', sending now: {}",
+                    notifier.name(),
+                    e
+                );
+                false
+            }
+        }
+    }
+
+    pub async fn notify_error(&self, site: &WatchedSite, error: &str) {
+        for notifier in self.routed(site) {
+            let alert = Pending::Error {
+                site: site.clone(),
+                error: error.to_string(),
+            };
+            if self.hold(notifier, site, alert) {
+                continue;
+            }
+            if let Err(e) = notifier.send_error(site, error).await {
+                error!("Failed to send error via '{}': {}", notifier.name(), e);
+            }
+        }
+    }
+
+    pub async fn notify_recovery(&self, site: &WatchedSite) {
+        for notifier in self.routed(site) {
+            if self.hold(notifier, site, Pending::Recovery { site: site.clone() }) {
+                continue;
+            }
+            if let Err(e) = notifier.send_recovery(site).await {
+                error!("Failed to send recovery via '{}': {}", notifier.name(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::FileStorage;
+    use std::sync::{Arc, Mutex};
+
+    fn site() -> WatchedSite {
+        WatchedSite::new(
+            "https://example.com".to_string(),
+            "Example".to_string(),
+            None,
+        )
+    }
+
+    fn change(content: &str) -> SiteChange {
+        SiteChange {
+            site: site(),
+            diff: SiteDiff::compute("", content),
+            old_content: String::new(),
+            new_content: content.to_string(),
+            detected_at: Utc::now(),
+        }
+    }
+
+    /// Records digests, failing after delivering `fail_after` changes.
+    struct Recorder {
+        fail_after: Option<usize>,
+        sent: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl Notifier for Recorder {
+        fn name(&self) -> &str {
+            "recorder"
+        }
+
+        async fn send_change(&self, change: &SiteChange) -> Result<()> {
+            if self.fail_after == Some(self.sent.lock().unwrap().len()) {
+                anyhow::bail!("unreachable");
+            }
+            self.sent.lock().unwrap().push(change.diff.added.join(""));
+            Ok(())
+        }
+
+        async fn send_error(&self, _site: &WatchedSite, error: &str) -> Result<()> {
+            self.sent.lock().unwrap().push(format!("error: {}", error));
+            Ok(())
+        }
+
+        async fn send_recovery(&self, _site: &WatchedSite) -> Result<()> {
+            self.sent.lock().unwrap().push("recovery".to_string());
+            Ok(())
+        }
+    }
+
+    fn batched(notifier: Recorder, dir: &std::path::Path) -> Notifiers {
+        Notifiers {
+            notifiers: vec![Box::new(notifier)],
+            delivery: BTreeMap::from([(
+                "recorder".to_string(),
+                DeliveryMode::Batched { window_mins: 0 },
+            )]),
+            quiet: BTreeMap::new(),
+            queue: Some(Box::new(FileStorage::new(
+                dir.to_path_buf(),
+                dir.to_path_buf(),
+            ))),
+        }
+    }
+
+    fn queued(dir: &std::path::Path) -> Vec<String> {
+        FileStorage::new(dir.to_path_buf(), dir.to_path_buf())
+            .load_pending("recorder")
+            .unwrap()
+            .into_iter()
+            .map(|alert| match alert {
+                Pending::Change(change) => change.diff.added.join(""),
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn queued_changes_are_sent_without_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let notifiers = batched(
+            Recorder {
+                fail_after: None,
+                sent: Default::default(),
+            },
+            dir.path(),
+        );
+
+        notifiers.notify_change(&change("a")).await;
+        let queue = FileStorage::new(dir.path().to_path_buf(), dir.path().to_path_buf());
+        match &queue.load_pending("recorder").unwrap()[..] {
+            [Pending::Change(queued)] => assert!(queued.new_content.is_empty()),
+            other => panic!("unexpected queue {:?}", other),
+        }
+
+        notifiers.flush_due().await;
+        assert!(queued(dir.path()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn partly_sent_digests_keep_the_rest_queued() {
+        let dir = tempfile::tempdir().unwrap();
+        let notifiers = batched(
+            Recorder {
+                fail_after: Some(1),
+                sent: Default::default(),
+            },
+            dir.path(),
+        );
+
+        for content in ["a", "b", "c"] {
+            notifiers.notify_change(&change(content)).await;
+        }
+        notifiers.flush_due().await;
+
+        assert_eq!(queued(dir.path()), ["b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn queued_alerts_are_sent_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let recorder = Recorder {
+            fail_after: None,
+            sent: sent.clone(),
+        };
+        let queue = FileStorage::new(dir.path().to_path_buf(), dir.path().to_path_buf());
+        for alert in [
+            Pending::Change(change("a")),
+            Pending::Error {
+                site: site(),
+                error: "timed out".to_string(),
+            },
+            Pending::Recovery { site: site() },
+            Pending::Change(change("b")),
+        ] {
+            queue.queue_pending("recorder", &alert).unwrap();
+        }
+
+        batched(recorder, dir.path()).flush_due().await;
+
+        assert_eq!(
+            *sent.lock().unwrap(),
+            ["a", "error: timed out", "recovery", "b"]
+        );
+        assert!(queued(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn pack_keeps_items_whole() {
+        let runs = pack(vec!["aaa", "bb", "cccc", "d"], 5, |item| item.len());
+        assert_eq!(runs, [vec!["aaa", "bb"], vec!["cccc", "d"]]);
+
+        // An item over the limit still gets a run of its own
+        let runs = pack(vec!["a", "bbbbbbb", "c"], 5, |item| item.len());
+        assert_eq!(runs, [vec!["a"], vec!["bbbbbbb"], vec!["c"]]);
+        assert!(pack(Vec::<&str>::new(), 5, |item| item.len()).is_empty());
+    }
+
+    #[test]
+    fn queued_alerts_round_trip() {
+        let change = SiteChange {
+            site: site(),
+            diff: SiteDiff::compute("a", "b"),
+            old_content: "a".to_string(),
+            new_content: "b".to_string(),
+            detected_at: Utc::now(),
+        };
+        let alerts = [
+            Pending::Change(change.clone()),
+            Pending::Error {
+                site: site(),
+                error: "timed out".to_string(),
+            },
+            Pending::Recovery { site: site() },
+        ];
+
+        for alert in &alerts {
+            let json = serde_json::to_string(alert).unwrap();
+            let parsed: Pending = serde_json::from_str(&json).unwrap();
+            assert_eq!(serde_json::to_string(&parsed).unwrap(), json);
+        }
+    }
+}
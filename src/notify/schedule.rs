@@ -0,0 +1,180 @@
+use crate::config::{DeliveryMode, QuietHours};
+use anyhow::Result;
+use chrono::{DateTime, Local, NaiveTime, Utc};
+use chrono_tz::Tz;
+
+pub fn parse_time(time: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M")
+        .map_err(|_| anyhow::anyhow!("Invalid time '{}', expected HH:MM", time))
+}
+
+/// The first time of day `at` ("HH:MM", local time) at or after `after`.
+fn next_digest_time(at: &str, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let time = parse_time(at).ok()?;
+    let after_local = after.with_timezone(&Local);
+
+    let mut date = after_local.date_naive();
+    // Two days cover `at` having passed today; a third skips a DST gap
+    for _ in 0..3 {
+        if let Some(candidate) = date.and_time(time).and_local_timezone(Local).earliest() {
+            if candidate >= after_local {
+                return Some(candidate.with_timezone(&Utc));
+            }
+        }
+        date = date.succ_opt()?;
+    }
+    None
+}
+
+/// Whether changes queued since `first` should be sent at `now`.
+pub fn is_due(mode: &DeliveryMode, first: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+    match mode {
+        DeliveryMode::Immediate => true,
+        DeliveryMode::Batched { window_mins } => {
+            now - first >= chrono::Duration::minutes(*window_mins as i64)
+        }
+        DeliveryMode::Digest { at } => next_digest_time(at, first).is_none_or(|due| now >= due),
+    }
+}
+
+/// Parsed [`QuietHours`]. A window whose end is before its start spans
+/// midnight.
+#[derive(Debug, Clone)]
+pub struct QuietWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+    timezone: Option<Tz>,
+}
+
+impl QuietWindow {
+    pub fn parse(hours: &QuietHours) -> Result<Self> {
+        let timezone = match hours.timezone {
+            Some(ref name) => Some(
+                name.parse::<Tz>()
+                    .map_err(|_| anyhow::anyhow!("Unknown timezone '{}'", name))?,
+            ),
+            None => None,
+        };
+
+        Ok(Self {
+            start: parse_time(&hours.start)?,
+            end: parse_time(&hours.end)?,
+            timezone,
+        })
+    }
+
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        let time = match self.timezone {
+            Some(tz) => now.with_timezone(&tz).time(),
+            None => now.with_timezone(&Local).time(),
+        };
+
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn window(start: &str, end: &str, timezone: &str) -> QuietWindow {
+        QuietWindow::parse(&QuietHours {
+            start: start.to_string(),
+            end: end.to_string(),
+            timezone: Some(timezone.to_string()),
+        })
+        .unwrap()
+    }
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+    }
+
+    #[test]
+    fn batches_are_due_after_their_window() {
+        let first = utc(2026, 1, 5, 10, 0);
+        let batched = DeliveryMode::Batched { window_mins: 30 };
+
+        assert!(is_due(&DeliveryMode::Immediate, first, first));
+        assert!(!is_due(&batched, first, utc(2026, 1, 5, 10, 29)));
+        assert!(is_due(&batched, first, utc(2026, 1, 5, 10, 30)));
+    }
+
+    #[test]
+    fn digests_are_due_at_the_next_digest_time() {
+        let local = |d: u32, h: u32, min: u32| {
+            Local
+                .with_ymd_and_hms(2026, 1, d, h, min, 0)
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+        let digest = DeliveryMode::Digest {
+            at: "09:00".to_string(),
+        };
+
+        // Queued after today's digest time: due tomorrow
+        assert!(!is_due(&digest, local(5, 10, 0), local(5, 23, 0)));
+        assert!(!is_due(&digest, local(5, 10, 0), local(6, 8, 59)));
+        assert!(is_due(&digest, local(5, 10, 0), local(6, 9, 0)));
+        // Queued before it: due today
+        assert!(is_due(&digest, local(5, 8, 0), local(5, 9, 0)));
+        // An unparsable time never holds changes back
+        let broken = DeliveryMode::Digest {
+            at: "nine".to_string(),
+        };
+        assert!(is_due(&broken, local(5, 8, 0), local(5, 8, 0)));
+    }
+
+    #[test]
+    fn quiet_window_within_a_day() {
+        let quiet = window("12:00", "14:00", "UTC");
+        assert!(!quiet.contains(utc(2026, 1, 5, 11, 59)));
+        assert!(quiet.contains(utc(2026, 1, 5, 12, 0)));
+        assert!(quiet.contains(utc(2026, 1, 5, 13, 59)));
+        assert!(!quiet.contains(utc(2026, 1, 5, 14, 0)));
+    }
+
+    #[test]
+    fn quiet_window_across_midnight() {
+        let quiet = window("22:00", "07:00", "UTC");
+        assert!(quiet.contains(utc(2026, 1, 5, 22, 0)));
+        assert!(quiet.contains(utc(2026, 1, 5, 23, 30)));
+        assert!(quiet.contains(utc(2026, 1, 6, 0, 0)));
+        assert!(quiet.contains(utc(2026, 1, 6, 6, 59)));
+        assert!(!quiet.contains(utc(2026, 1, 6, 7, 0)));
+        assert!(!quiet.contains(utc(2026, 1, 6, 12, 0)));
+        assert!(!quiet.contains(utc(2026, 1, 6, 21, 59)));
+    }
+
+    #[test]
+    fn quiet_window_follows_dst() {
+        // Berlin moves from UTC+1 to UTC+2 at 01:00 UTC on 29 March 2026
+        let quiet = window("22:00", "07:00", "Europe/Berlin");
+        assert!(quiet.contains(utc(2026, 3, 28, 21, 0)));
+        assert!(!quiet.contains(utc(2026, 3, 28, 20, 59)));
+        assert!(quiet.contains(utc(2026, 3, 29, 4, 59)));
+        assert!(!quiet.contains(utc(2026, 3, 29, 5, 0)));
+        // And back to UTC+1 at 01:00 UTC on 25 October 2026
+        assert!(quiet.contains(utc(2026, 10, 25, 5, 59)));
+        assert!(!quiet.contains(utc(2026, 10, 25, 6, 0)));
+    }
+
+    #[test]
+    fn quiet_window_rejects_bad_input() {
+        let parse = |start: &str, end: &str, timezone: Option<&str>| {
+            QuietWindow::parse(&QuietHours {
+                start: start.to_string(),
+                end: end.to_string(),
+                timezone: timezone.map(str::to_string),
+            })
+        };
+        assert!(parse("25:00", "07:00", None).is_err());
+        assert!(parse("22:00", "7", None).is_err());
+        assert!(parse("22:00", "07:00", Some("Mars/Olympus")).is_err());
+    }
+}
@@ -0,0 +1,152 @@
+use super::{
+    http_client, pack, truncate_chars, Notifier, PartialDigest, SiteChange, DIGEST_DIFF_CHARS,
+};
+use crate::config::WatchedSite;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use reqwest::Client;
+use serde_json::{json, Value};
+
+// Slack rejects section blocks with more than 3000 characters
+const MAX_SECTION_CHARS: usize = 3000;
+// and messages with more than 50 blocks
+const MAX_DIGEST_SECTIONS: usize = 40;
+
+/// Posts Block Kit messages to a Slack incoming webhook.
+pub struct SlackNotifier {
+    client: Client,
+    name: String,
+    webhook_url: String,
+}
+
+impl SlackNotifier {
+    pub fn new(name: String, webhook_url: String) -> Self {
+        Self {
+            client: http_client(),
+            name,
+            webhook_url,
+        }
+    }
+
+    async fn post(&self, text: String, blocks: Vec<Value>) -> Result<()> {
+        let payload = json!({ "text": text, "blocks": blocks });
+        let response = self
+            .client
+            .post(&self.webhook_url)
+            .json(&payload)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error = response.text().await.unwrap_or_default();
+            return Err(anyhow::anyhow!("Slack returned {}: {}", status, error));
+        }
+
+        Ok(())
+    }
+}
+
+fn escape_mrkdwn(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn header(text: &str) -> Value {
+    json!({
+        "type": "header",
+        "text": { "type": "plain_text", "text": truncate_chars(text, 150) },
+    })
+}
+
+fn section(mrkdwn: String) -> Value {
+    json!({
+        "type": "section",
+        "text": { "type": "mrkdwn", "text": truncate_chars(&mrkdwn, MAX_SECTION_CHARS) },
+    })
+}
+
+fn site_section(site: &WatchedSite) -> Value {
+    section(format!(
+        "*Site:* <{}|{}>\n*Time:* {}",
+        escape_mrkdwn(&site.url),
+        escape_mrkdwn(&site.name),
+        Utc::now().format("%Y-%m-%d %H:%M:%S UTC")
+    ))
+}
+
+#[async_trait]
+impl Notifier for SlackNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn send_change(&self, change: &SiteChange) -> Result<()> {
+        let diff = truncate_chars(
+            &escape_mrkdwn(&change.diff.to_string()),
+            MAX_SECTION_CHARS - 6,
+        );
+
+        self.post(
+            format!("Change detected: {}", change.site.name),
+            vec![
+                header(&format!("🔄 Change detected: {}", change.site.name)),
+                site_section(&change.site),
+                section(format!("```{}```", diff)),
+            ],
+        )
+        .await
+    }
+
+    async fn send_digest(&self, changes: &[SiteChange]) -> Result<()> {
+        let title = format!("{} change(s)", changes.len());
+        let mut sent = 0;
+
+        for run in pack(changes.iter().collect(), MAX_DIGEST_SECTIONS, |_| 1) {
+            let mut blocks = vec![header(&format!("📬 {}", title))];
+            for change in &run {
+                let diff =
+                    truncate_chars(&escape_mrkdwn(&change.diff.to_string()), DIGEST_DIFF_CHARS);
+                blocks.push(section(format!(
+                    "*<{}|{}>* ({})\n```{}```",
+                    escape_mrkdwn(&change.site.url),
+                    escape_mrkdwn(&change.site.name),
+                    change.detected_at.format("%Y-%m-%d %H:%M UTC"),
+                    diff
+                )));
+            }
+
+            self.post(title.clone(), blocks)
+                .await
+                .map_err(|error| PartialDigest { sent, error })?;
+            sent += run.len();
+        }
+
+        Ok(())
+    }
+
+    async fn send_error(&self, site: &WatchedSite, error: &str) -> Result<()> {
+        self.post(
+            format!("Check failing: {}", site.name),
+            vec![
+                header(&format!("⚠️ Check failing: {}", site.name)),
+                site_section(site),
+                section(format!("*Error:* {}", escape_mrkdwn(error))),
+            ],
+        )
+        .await
+    }
+
+    async fn send_recovery(&self, site: &WatchedSite) -> Result<()> {
+        self.post(
+            format!("Check recovered: {}", site.name),
+            vec![
+                header(&format!("✅ Check recovered: {}", site.name)),
+                site_section(site),
+            ],
+        )
+        .await
+    }
+}
@@ -0,0 +1,246 @@
+use super::{http_client, Notifier, SiteChange};
+use crate::config::WatchedSite;
+use crate::crypto::hmac_sha256;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+const SIGNATURE_HEADER: &str = "X-RNOT-Signature";
+
+/// POSTs a JSON payload for every event to a configured URL.
+///
+/// Templates are JSON documents with `{{placeholder}}` markers, each replaced
+/// by the JSON encoding of its value: `event`, `site_id`, `site_name`, `url`,
+/// `timestamp`, `diff` (rendered text), `added`, `removed`, `error` and
+/// `changes`. A digest (event `digest`) has no single site; its `changes`
+/// holds one object per change with the site and diff fields, and `diff`
+/// all of their diffs.
+pub struct WebhookNotifier {
+    client: Client,
+    name: String,
+    url: String,
+    headers: BTreeMap<String, String>,
+    template: Option<String>,
+    secret: Option<String>,
+}
+
+impl WebhookNotifier {
+    pub fn new(
+        name: String,
+        url: String,
+        headers: BTreeMap<String, String>,
+        template: Option<String>,
+        secret: Option<String>,
+    ) -> Self {
+        Self {
+            client: http_client(),
+            name,
+            url,
+            headers,
+            template,
+            secret,
+        }
+    }
+
+    fn render(&self, fields: &Value) -> Result<String> {
+        let template = match self.template {
+            Some(ref template) => template,
+            None => return Ok(fields.to_string()),
+        };
+
+        // One pass over the template, so markers inside substituted values
+        // are left alone
+        let mut body = String::with_capacity(template.len());
+        let mut rest = template.as_str();
+        while let Some(start) = rest.find("{{") {
+            body.push_str(&rest[..start]);
+            let marker = &rest[start..];
+            let value = marker
+                .find("}}")
+                .and_then(|end| Some((fields.get(&marker[2..end])?, end + 2)));
+            match value {
+                Some((value, len)) => {
+                    body.push_str(&value.to_string());
+                    rest = &marker[len..];
+                }
+                None => {
+                    body.push_str("{{");
+                    rest = &marker[2..];
+                }
+            }
+        }
+        body.push_str(rest);
+
+        serde_json::from_str::<Value>(&body).map_err(|e| {
+            anyhow::anyhow!(
+                "Webhook template for '{}' is not valid JSON: {}",
+                self.name,
+                e
+            )
+        })?;
+
+        Ok(body)
+    }
+
+    async fn post(&self, fields: Value) -> Result<()> {
+        let body = self.render(&fields)?;
+
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json");
+
+        for (key, value) in &self.headers {
+            request = request.header(key, value);
+        }
+
+        if let Some(ref secret) = self.secret {
+            let signature = hmac_sha256(secret.as_bytes(), body.as_bytes());
+            request = request.header(
+                SIGNATURE_HEADER,
+                format!("sha256={}", hex::encode(signature)),
+            );
+        }
+
+        let response = request.body(body).send().await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error = response.text().await.unwrap_or_default();
+            return Err(anyhow::anyhow!("Webhook returned {}: {}", status, error));
+        }
+
+        Ok(())
+    }
+}
+
+fn site_fields(event: &str, site: &WatchedSite, timestamp: DateTime<Utc>) -> Value {
+    json!({
+        "event": event,
+        "site_id": site.id,
+        "site_name": site.name,
+        "url": site.url,
+        "timestamp": timestamp.to_rfc3339(),
+        "diff": null,
+        "added": null,
+        "removed": null,
+        "error": null,
+        "changes": null,
+    })
+}
+
+fn change_fields(change: &SiteChange) -> Value {
+    json!({
+        "site_id": change.site.id,
+        "site_name": change.site.name,
+        "url": change.site.url,
+        "timestamp": change.detected_at.to_rfc3339(),
+        "diff": change.diff.to_string(),
+        "added": change.diff.added,
+        "removed": change.diff.removed,
+    })
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn send_change(&self, change: &SiteChange) -> Result<()> {
+        let mut fields = site_fields("change", &change.site, change.detected_at);
+        fields["diff"] = json!(change.diff.to_string());
+        fields["added"] = json!(change.diff.added);
+        fields["removed"] = json!(change.diff.removed);
+
+        self.post(fields).await
+    }
+
+    async fn send_digest(&self, changes: &[SiteChange]) -> Result<()> {
+        let diff: Vec<String> = changes
+            .iter()
+            .map(|change| {
+                format!(
+                    "{} ({})\n{}",
+                    change.site.name, change.site.url, change.diff
+                )
+            })
+            .collect();
+
+        self.post(json!({
+            "event": "digest",
+            "site_id": null,
+            "site_name": null,
+            "url": null,
+            "timestamp": Utc::now().to_rfc3339(),
+            "diff": diff.join("\n\n"),
+            "added": null,
+            "removed": null,
+            "error": null,
+            "changes": changes.iter().map(change_fields).collect::<Vec<_>>(),
+        }))
+        .await
+    }
+
+    async fn send_error(&self, site: &WatchedSite, error: &str) -> Result<()> {
+        let mut fields = site_fields("error", site, Utc::now());
+        fields["error"] = json!(error);
+
+        self.post(fields).await
+    }
+
+    async fn send_recovery(&self, site: &WatchedSite) -> Result<()> {
+        self.post(site_fields("recovery", site, Utc::now())).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notifier(template: Option<&str>) -> WebhookNotifier {
+        WebhookNotifier::new(
+            "ops".to_string(),
+            "https://example.com/hook".to_string(),
+            BTreeMap::new(),
+            template.map(str::to_string),
+            None,
+        )
+    }
+
+    #[test]
+    fn render_substitutes_json_values() {
+        let webhook = notifier(Some(r#"{"text": {{site_name}}, "n": {{added}}}"#));
+        let body = webhook
+            .render(&json!({"site_name": "Blog \"x\"", "added": ["a"]}))
+            .unwrap();
+        assert_eq!(body, r#"{"text": "Blog \"x\"", "n": ["a"]}"#);
+    }
+
+    #[test]
+    fn render_does_not_substitute_inside_values() {
+        let webhook = notifier(Some(r#"{"error": {{error}}, "name": {{site_name}}}"#));
+        let body = webhook
+            .render(&json!({"error": "{{site_name}}", "site_name": "Blog"}))
+            .unwrap();
+        assert_eq!(body, r#"{"error": "{{site_name}}", "name": "Blog"}"#);
+    }
+
+    #[test]
+    fn render_keeps_unknown_markers_and_checks_json() {
+        let webhook = notifier(Some(r#"{"a": "{{nope}}", "b": {{event}}}"#));
+        let body = webhook.render(&json!({"event": "change"})).unwrap();
+        assert_eq!(body, r#"{"a": "{{nope}}", "b": "change"}"#);
+
+        assert!(notifier(Some("{{event}"))
+            .render(&json!({"event": 1}))
+            .is_err());
+        assert_eq!(
+            notifier(None).render(&json!({"event": "change"})).unwrap(),
+            r#"{"event":"change"}"#
+        );
+    }
+}
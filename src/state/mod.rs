@@ -0,0 +1,196 @@
+use crate::config::WatchedSite;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// What the monitor and the bot learn about a site at runtime, kept out of
+/// config.toml so checks never rewrite the file users edit.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SiteState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_checked: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_change: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub muted: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snoozed_until: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+impl SiteState {
+    pub fn of(site: &WatchedSite) -> Self {
+        Self {
+            last_hash: site.last_hash.clone(),
+            last_checked: site.last_checked,
+            last_change: site.last_change,
+            last_error: site.last_error.clone(),
+            muted: site.muted,
+            snoozed_until: site.snoozed_until,
+            last_seen: site.last_seen,
+        }
+    }
+
+    pub fn apply(self, site: &mut WatchedSite) {
+        site.last_hash = self.last_hash;
+        site.last_checked = self.last_checked;
+        site.last_change = self.last_change;
+        site.last_error = self.last_error;
+        site.muted = self.muted;
+        site.snoozed_until = self.snoozed_until;
+        site.last_seen = self.last_seen;
+    }
+}
+
+/// One JSON file of [`SiteState`] per site under `data_dir/state`.
+///
+/// The monitor and the bot write different fields of the same file, each
+/// from a copy of the site that may be minutes old. Both go through
+/// [`StateStore::update`], which only changes the fields its caller owns.
+pub struct StateStore {
+    state_dir: PathBuf,
+}
+
+impl StateStore {
+    pub fn new(data_dir: &std::path::Path) -> Self {
+        Self {
+            state_dir: data_dir.join("state"),
+        }
+    }
+
+    pub fn load(&self, site_id: &str) -> Result<Option<SiteState>> {
+        let state_file = self.state_file(site_id)?;
+
+        if !state_file.exists() {
+            return Ok(None);
+        }
+
+        Ok(Some(serde_json::from_str(&std::fs::read_to_string(
+            &state_file,
+        )?)?))
+    }
+
+    /// Fills in the runtime state of each site that has any stored.
+    pub fn apply_all(&self, sites: &mut [WatchedSite]) {
+        for site in sites {
+            match self.load(&site.id) {
+                Ok(Some(state)) => state.apply(site),
+                Ok(None) => {}
+                Err(e) => tracing::warn!("Failed to load state of {}: {}", site.id, e),
+            }
+        }
+    }
+
+    /// Saves the check results of `site`: the fields the monitor owns.
+    pub fn save_checks(&self, site: &WatchedSite) -> Result<()> {
+        self.update(&site.id, |state| {
+            state.last_hash = site.last_hash.clone();
+            state.last_checked = site.last_checked;
+            state.last_change = site.last_change;
+            state.last_error = site.last_error.clone();
+        })
+    }
+
+    /// Saves the mute, snooze and last seen time of `site`: the fields
+    /// users change from the bot.
+    pub fn save_alerts(&self, site: &WatchedSite) -> Result<()> {
+        self.update(&site.id, |state| {
+            state.muted = site.muted;
+            state.snoozed_until = site.snoozed_until;
+            state.last_seen = site.last_seen;
+        })
+    }
+
+    /// Applies `f` to the stored state of a site, holding the store's lock
+    /// from the read to the write.
+    pub fn update(&self, site_id: &str, f: impl FnOnce(&mut SiteState)) -> Result<()> {
+        let _lock = self.lock()?;
+        let mut state = self.load(site_id)?.unwrap_or_default();
+        f(&mut state);
+        self.write(site_id, &state)
+    }
+
+    /// Replaces the whole stored state of a site.
+    pub fn store(&self, site_id: &str, state: &SiteState) -> Result<()> {
+        let _lock = self.lock()?;
+        self.write(site_id, state)
+    }
+
+    /// Takes the lock every writer holds; released when the file is dropped.
+    fn lock(&self) -> Result<File> {
+        std::fs::create_dir_all(&self.state_dir)?;
+
+        let lock = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.state_dir.join("state.lock"))?;
+        lock.lock()?;
+
+        Ok(lock)
+    }
+
+    /// Atomically replaces the state file. Callers must hold the lock.
+    fn write(&self, site_id: &str, state: &SiteState) -> Result<()> {
+        let state_file = self.state_file(site_id)?;
+
+        let mut temp = tempfile::NamedTempFile::new_in(&self.state_dir)?;
+        temp.write_all(serde_json::to_string_pretty(state)?.as_bytes())?;
+        temp.persist(&state_file)?;
+
+        Ok(())
+    }
+
+    pub fn remove(&self, site_id: &str) -> Result<()> {
+        let state_file = self.state_file(site_id)?;
+
+        if state_file.exists() {
+            std::fs::remove_file(&state_file)?;
+        }
+
+        Ok(())
+    }
+
+    fn state_file(&self, site_id: &str) -> Result<PathBuf> {
+        if site_id.is_empty()
+            || site_id.len() > 64
+            || !site_id.chars().all(|c| c.is_ascii_alphanumeric())
+        {
+            anyhow::bail!("Invalid site ID: {}", site_id);
+        }
+        Ok(self.state_dir.join(format!("{}.json", site_id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writers_keep_each_others_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StateStore::new(dir.path());
+        let site = WatchedSite::new("https://example.com".into(), "Example".into(), None);
+
+        // The bot and the monitor each start from the same stale copy
+        let mut snoozed = site.clone();
+        snoozed.snoozed_until = Some(Utc::now());
+        store.save_alerts(&snoozed).unwrap();
+
+        let mut checked = site.clone();
+        checked.last_hash = Some("abc".into());
+        store.save_checks(&checked).unwrap();
+
+        let state = store.load(&site.id).unwrap().unwrap();
+        assert_eq!(state.last_hash.as_deref(), Some("abc"));
+        assert_eq!(state.snoozed_until, snoozed.snoozed_until);
+    }
+}
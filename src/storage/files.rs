@@ -0,0 +1,691 @@
+use super::{
+    compress, decompress, read_config, retained, retention_cutoff, CheckRecord, GcReport,
+    RawResponse, Storage,
+};
+use crate::config::{AppConfig, Retention, WatchedSite};
+use crate::notify::Pending;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::collections::HashSet;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Blobs this recently written or reused are never collected, so a
+/// snapshot saved while `gc` runs cannot lose its contents.
+const GC_GRACE: Duration = Duration::from_secs(3600);
+
+/// How much of a history file `recent_history` reads at a time, from the
+/// end.
+const TAIL_CHUNK: u64 = 64 * 1024;
+
+/// Plain files under the data directory: last diff, check history (JSONL),
+/// gzipped archived responses and notification queue per site or notifier.
+/// Snapshots are gzipped blobs named by content hash, shared between sites
+/// and versions, and `snapshots/<id>.ref` holds the hash of a site's latest
+/// one.
+pub struct FileStorage {
+    data_dir: PathBuf,
+    config_dir: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(data_dir: PathBuf, config_dir: PathBuf) -> Self {
+        Self {
+            data_dir,
+            config_dir,
+        }
+    }
+
+    fn pending_file(&self, name: &str) -> Result<PathBuf> {
+        if name.is_empty()
+            || name.len() > 64
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            anyhow::bail!("Notifier name '{}' cannot be used for a queue file", name);
+        }
+        Ok(self
+            .data_dir
+            .join("pending")
+            .join(format!("{}.jsonl", name)))
+    }
+
+    fn blob_file(&self, hash: &str) -> Result<PathBuf> {
+        if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            anyhow::bail!("Invalid content hash: {}", hash);
+        }
+        Ok(self
+            .data_dir
+            .join("blobs")
+            .join(&hash[..2])
+            .join(format!("{}.gz", hash)))
+    }
+
+    fn snapshot_ref(&self, site_id: &str) -> Result<Option<String>> {
+        let ref_file = self
+            .data_dir
+            .join("snapshots")
+            .join(format!("{}.ref", site_id));
+
+        if !ref_file.exists() {
+            return Ok(None);
+        }
+
+        Ok(Some(std::fs::read_to_string(&ref_file)?.trim().to_string()))
+    }
+
+    /// Held while writing check history, so trimming it cannot lose a
+    /// check recorded at the same time.
+    fn lock_history(&self) -> Result<std::fs::File> {
+        let history_dir = self.data_dir.join("history");
+        std::fs::create_dir_all(&history_dir)?;
+
+        let lock = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(history_dir.join(".lock"))?;
+        lock.lock()?;
+
+        Ok(lock)
+    }
+
+    /// Takes the lock every writer of the pending queues holds.
+    fn lock_pending(&self) -> Result<std::fs::File> {
+        let pending_dir = self.data_dir.join("pending");
+        std::fs::create_dir_all(&pending_dir)?;
+
+        let lock = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(pending_dir.join(".lock"))?;
+        lock.lock()?;
+
+        Ok(lock)
+    }
+
+    /// Replaces the history file of a site. Callers must hold the history
+    /// lock.
+    fn write_history(&self, site_id: &str, records: &[CheckRecord]) -> Result<()> {
+        let history_dir = self.data_dir.join("history");
+
+        let mut temp = tempfile::NamedTempFile::new_in(&history_dir)?;
+        for record in records {
+            writeln!(temp, "{}", serde_json::to_string(record)?)?;
+        }
+        temp.persist(history_dir.join(format!("{}.jsonl", site_id)))?;
+
+        Ok(())
+    }
+
+    /// Drops the checks of a site `retention` does not keep; returns how
+    /// many went.
+    fn trim_history(
+        &self,
+        site_id: &str,
+        retention: &Retention,
+        now: DateTime<Utc>,
+    ) -> Result<usize> {
+        let _lock = self.lock_history()?;
+        let history = self.load_history(site_id)?;
+        let kept = retained(&history, retention, now);
+
+        if kept.len() < history.len() {
+            self.write_history(site_id, kept)?;
+        }
+
+        Ok(history.len() - kept.len())
+    }
+
+    /// Deletes the archived responses of a site fetched before `cutoff`;
+    /// returns how many went and their size.
+    fn trim_archive(&self, site_id: &str, cutoff: DateTime<Utc>) -> Result<(usize, u64)> {
+        let archive_dir = self.data_dir.join("archive").join(site_id);
+        let mut trimmed = (0, 0);
+
+        if !archive_dir.exists() {
+            return Ok(trimmed);
+        }
+
+        for entry in std::fs::read_dir(&archive_dir)? {
+            let path = entry?.path();
+            // Named `<millis>-<seq>.json.gz` after the fetch time
+            let fetched_millis = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.split('-').next())
+                .and_then(|millis| millis.parse::<i64>().ok());
+
+            if fetched_millis.is_some_and(|millis| millis < cutoff.timestamp_millis()) {
+                trimmed.1 += path.metadata()?.len();
+                std::fs::remove_file(&path)?;
+                trimmed.0 += 1;
+            }
+        }
+
+        Ok(trimmed)
+    }
+
+    fn validate_site_id(site_id: &str) -> Result<()> {
+        if site_id.is_empty() || site_id.len() > 64 {
+            anyhow::bail!("Invalid site ID length");
+        }
+        if !site_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            anyhow::bail!("Site ID contains invalid characters");
+        }
+        Ok(())
+    }
+}
+
+impl Storage for FileStorage {
+    fn save_snapshot(&self, site_id: &str, hash: &str, content: &str) -> Result<()> {
+        Self::validate_site_id(site_id)?;
+
+        let blob_file = self.blob_file(hash)?;
+        if blob_file.exists() {
+            std::fs::File::options()
+                .write(true)
+                .open(&blob_file)?
+                .set_modified(SystemTime::now())?;
+        } else {
+            let blob_dir = blob_file.parent().expect("blob files are in a directory");
+            std::fs::create_dir_all(blob_dir)?;
+
+            let mut temp = tempfile::NamedTempFile::new_in(blob_dir)?;
+            temp.write_all(&compress(content)?)?;
+            temp.persist(&blob_file)?;
+        }
+
+        let snapshot_dir = self.data_dir.join("snapshots");
+        std::fs::create_dir_all(&snapshot_dir)?;
+        std::fs::write(snapshot_dir.join(format!("{}.ref", site_id)), hash)?;
+
+        // Written by versions that kept one uncompressed file per site
+        let legacy_file = snapshot_dir.join(format!("{}.txt", site_id));
+        if legacy_file.exists() {
+            std::fs::remove_file(&legacy_file)?;
+        }
+
+        Ok(())
+    }
+
+    fn get_snapshot(&self, site_id: &str) -> Result<String> {
+        Self::validate_site_id(site_id)?;
+
+        if let Some(hash) = self.snapshot_ref(site_id)? {
+            return decompress(&std::fs::read(self.blob_file(&hash)?)?);
+        }
+
+        let legacy_file = self
+            .data_dir
+            .join("snapshots")
+            .join(format!("{}.txt", site_id));
+
+        if legacy_file.exists() {
+            Ok(std::fs::read_to_string(&legacy_file)?)
+        } else {
+            Ok(String::new())
+        }
+    }
+
+    fn save_diff(&self, site_id: &str, diff: &str) -> Result<()> {
+        Self::validate_site_id(site_id)?;
+
+        let diff_dir = self.data_dir.join("diffs");
+        std::fs::create_dir_all(&diff_dir)?;
+
+        std::fs::write(diff_dir.join(format!("{}.diff", site_id)), diff)?;
+
+        Ok(())
+    }
+
+    fn get_diff(&self, site_id: &str) -> Result<Option<String>> {
+        Self::validate_site_id(site_id)?;
+
+        let diff_file = self
+            .data_dir
+            .join("diffs")
+            .join(format!("{}.diff", site_id));
+
+        if diff_file.exists() {
+            Ok(Some(std::fs::read_to_string(&diff_file)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn record_check(&self, site_id: &str, record: &CheckRecord) -> Result<()> {
+        Self::validate_site_id(site_id)?;
+
+        let _lock = self.lock_history()?;
+        let history_file = self
+            .data_dir
+            .join("history")
+            .join(format!("{}.jsonl", site_id));
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&history_file)?;
+        writeln!(file, "{}", serde_json::to_string(record)?)?;
+
+        Ok(())
+    }
+
+    fn load_history(&self, site_id: &str) -> Result<Vec<CheckRecord>> {
+        Self::validate_site_id(site_id)?;
+
+        let history_file = self
+            .data_dir
+            .join("history")
+            .join(format!("{}.jsonl", site_id));
+
+        if !history_file.exists() {
+            return Ok(Vec::new());
+        }
+
+        // Skip lines that fail to parse (e.g. a partial write after a crash)
+        let content = std::fs::read_to_string(&history_file)?;
+        Ok(content
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    fn recent_history(&self, site_id: &str, limit: usize) -> Result<Vec<CheckRecord>> {
+        Self::validate_site_id(site_id)?;
+
+        let history_file = self
+            .data_dir
+            .join("history")
+            .join(format!("{}.jsonl", site_id));
+
+        if !history_file.exists() {
+            return Ok(Vec::new());
+        }
+
+        // Read chunks from the end until they hold `limit` whole lines
+        let mut file = std::fs::File::open(&history_file)?;
+        let mut start = file.metadata()?.len();
+        let mut tail = Vec::new();
+        let mut lines = 0;
+        while start > 0 && lines <= limit {
+            let chunk = TAIL_CHUNK.min(start);
+            start -= chunk;
+
+            let mut buf = vec![0; chunk as usize];
+            file.seek(SeekFrom::Start(start))?;
+            file.read_exact(&mut buf)?;
+            lines += buf.iter().filter(|&&b| b == b'\n').count();
+            buf.extend_from_slice(&tail);
+            tail = buf;
+        }
+
+        let content = String::from_utf8_lossy(&tail);
+        let mut records: Vec<CheckRecord> = content
+            .lines()
+            // The first line is cut off unless the start of the file was read
+            .skip(usize::from(start > 0))
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        records.drain(..records.len().saturating_sub(limit));
+
+        Ok(records)
+    }
+
+    fn replace_history(&self, site_id: &str, records: &[CheckRecord]) -> Result<()> {
+        Self::validate_site_id(site_id)?;
+
+        let _lock = self.lock_history()?;
+        self.write_history(site_id, records)
+    }
+
+    fn archive_response(&self, site_id: &str, response: &RawResponse) -> Result<()> {
+        Self::validate_site_id(site_id)?;
+
+        let archive_dir = self.data_dir.join("archive").join(site_id);
+        std::fs::create_dir_all(&archive_dir)?;
+
+        let blob = compress(&serde_json::to_string(response)?)?;
+        let millis = response.fetched_at.timestamp_millis();
+
+        // Responses fetched within the same millisecond get a sequence number
+        for seq in 0.. {
+            let file_name = format!("{}-{}.json.gz", millis, seq);
+            match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(archive_dir.join(file_name))
+            {
+                Ok(mut file) => {
+                    file.write_all(&blob)?;
+                    break;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn load_archive(&self, site_id: &str) -> Result<Vec<RawResponse>> {
+        Self::validate_site_id(site_id)?;
+
+        let archive_dir = self.data_dir.join("archive").join(site_id);
+
+        if !archive_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut responses = Vec::new();
+        for entry in std::fs::read_dir(&archive_dir)? {
+            let blob = std::fs::read(entry?.path())?;
+            match decompress(&blob).map(|json| serde_json::from_str::<RawResponse>(&json)) {
+                Ok(Ok(response)) => responses.push(response),
+                _ => tracing::warn!("Skipping unreadable archived response of {}", site_id),
+            }
+        }
+        responses.sort_by_key(|response| response.fetched_at);
+
+        Ok(responses)
+    }
+
+    fn queue_pending(&self, name: &str, alert: &Pending) -> Result<()> {
+        let _lock = self.lock_pending()?;
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.pending_file(name)?)?;
+        writeln!(file, "{}", serde_json::to_string(alert)?)?;
+
+        Ok(())
+    }
+
+    fn load_pending(&self, name: &str) -> Result<Vec<Pending>> {
+        let pending_file = self.pending_file(name)?;
+
+        if !pending_file.exists() {
+            return Ok(Vec::new());
+        }
+
+        Ok(std::fs::read_to_string(&pending_file)?
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    fn replace_pending(&self, name: &str, count: usize, unsent: &[Pending]) -> Result<()> {
+        let _lock = self.lock_pending()?;
+        let pending_file = self.pending_file(name)?;
+        let queued = self.load_pending(name)?;
+        let rest: Vec<&Pending> = unsent.iter().chain(queued.iter().skip(count)).collect();
+
+        if rest.is_empty() {
+            if pending_file.exists() {
+                std::fs::remove_file(&pending_file)?;
+            }
+            return Ok(());
+        }
+
+        let mut temp = tempfile::NamedTempFile::new_in(self.data_dir.join("pending"))?;
+        for alert in rest {
+            writeln!(temp, "{}", serde_json::to_string(alert)?)?;
+        }
+        temp.persist(&pending_file)?;
+
+        Ok(())
+    }
+
+    fn load_config(&self) -> Option<AppConfig> {
+        read_config(&self.config_dir, &self.data_dir)
+    }
+
+    fn gc(&self, sites: &[WatchedSite], retention: &Retention) -> Result<GcReport> {
+        let mut report = GcReport::default();
+        let now = Utc::now();
+
+        for site in sites {
+            report.checks_removed += self.trim_history(&site.id, retention, now)?;
+            if let Some(cutoff) = retention_cutoff(retention, now) {
+                let (removed, bytes) = self.trim_archive(&site.id, cutoff)?;
+                report.responses_removed += removed;
+                report.freed_bytes += bytes;
+            }
+        }
+
+        let blob_dir = self.data_dir.join("blobs");
+
+        if !blob_dir.exists() {
+            return Ok(report);
+        }
+
+        // List blobs before collecting references, so one saved in between
+        // is not mistaken for garbage
+        let mut blobs = Vec::new();
+        for dir in std::fs::read_dir(&blob_dir)? {
+            let dir = dir?.path();
+            if dir.is_dir() {
+                for entry in std::fs::read_dir(&dir)? {
+                    blobs.push(entry?.path());
+                }
+            }
+        }
+
+        // Older versions stay as long as the kept checks mention them
+        let mut referenced = HashSet::new();
+        for site in sites {
+            referenced.extend(site.last_hash.clone());
+            referenced.extend(self.snapshot_ref(&site.id)?);
+            referenced.extend(
+                self.load_history(&site.id)?
+                    .into_iter()
+                    .filter_map(|record| record.hash),
+            );
+        }
+
+        let site_ids: HashSet<&str> = sites.iter().map(|s| s.id.as_str()).collect();
+        let snapshot_dir = self.data_dir.join("snapshots");
+        if snapshot_dir.exists() {
+            for entry in std::fs::read_dir(&snapshot_dir)? {
+                let path = entry?.path();
+                let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+                if !site_ids.contains(stem) {
+                    std::fs::remove_file(&path)?;
+                }
+            }
+        }
+
+        let archive_dir = self.data_dir.join("archive");
+        if archive_dir.exists() {
+            for entry in std::fs::read_dir(&archive_dir)? {
+                let path = entry?.path();
+                let site_id = path.file_name().and_then(|s| s.to_str()).unwrap_or("");
+                if site_ids.contains(site_id) || !path.is_dir() {
+                    continue;
+                }
+
+                for file in std::fs::read_dir(&path)? {
+                    report.freed_bytes += file?.metadata()?.len();
+                }
+                std::fs::remove_dir_all(&path)?;
+                report.archives_removed += 1;
+            }
+        }
+
+        for blob in blobs {
+            let hash = blob.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+            if referenced.contains(hash) {
+                continue;
+            }
+
+            let metadata = blob.metadata()?;
+            if metadata.modified()?.elapsed().unwrap_or_default() < GC_GRACE {
+                continue;
+            }
+
+            std::fs::remove_file(&blob)?;
+            report.removed += 1;
+            report.freed_bytes += metadata.len();
+        }
+
+        Ok(report)
+    }
+
+    fn clear(&self) -> Result<()> {
+        for dir in [
+            "snapshots",
+            "blobs",
+            "diffs",
+            "history",
+            "archive",
+            "pending",
+        ] {
+            let dir = self.data_dir.join(dir);
+            if dir.exists() {
+                std::fs::remove_dir_all(&dir)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    fn check(content: &str, days_ago: i64) -> CheckRecord {
+        CheckRecord {
+            timestamp: Utc::now() - chrono::Duration::days(days_ago),
+            changed: true,
+            response_ms: 80,
+            value: None,
+            hash: Some(hex::encode(Sha256::digest(content.as_bytes()))),
+            error: None,
+        }
+    }
+
+    fn response(days_ago: i64) -> RawResponse {
+        RawResponse {
+            fetched_at: Utc::now() - chrono::Duration::days(days_ago),
+            status: 200,
+            headers: Vec::new(),
+            body: "<p>page</p>".to_string(),
+            hash: String::new(),
+        }
+    }
+
+    #[test]
+    fn recent_history_reads_only_the_end() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FileStorage::new(dir.path().to_path_buf(), dir.path().to_path_buf());
+
+        // Several chunks' worth
+        let records: Vec<CheckRecord> = (0..2000)
+            .map(|i| CheckRecord {
+                response_ms: i,
+                ..check("v1", 0)
+            })
+            .collect();
+        storage.replace_history("a1", &records).unwrap();
+
+        for limit in [0, 1, 5, 1500, 2000, 3000] {
+            let recent = storage.recent_history("a1", limit).unwrap();
+            let expected: Vec<u64> = (2000u64.saturating_sub(limit as u64)..2000).collect();
+            let got: Vec<u64> = recent.iter().map(|r| r.response_ms).collect();
+            assert_eq!(got, expected, "limit {}", limit);
+        }
+        assert!(storage.recent_history("b2", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn gc_trims_history_and_collects_what_only_it_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FileStorage::new(dir.path().to_path_buf(), dir.path().to_path_buf());
+        let site = WatchedSite::new("https://example.com".to_string(), "Ex".to_string(), None);
+
+        for (content, days_ago) in [("v1", 3), ("v2", 2), ("v3", 1)] {
+            let record = check(content, days_ago);
+            let hash = record.hash.clone().unwrap();
+            storage.save_snapshot(&site.id, &hash, content).unwrap();
+            storage.record_check(&site.id, &record).unwrap();
+        }
+        // Past the grace period
+        let written = SystemTime::now() - 2 * GC_GRACE;
+        for dir in std::fs::read_dir(dir.path().join("blobs")).unwrap() {
+            for blob in std::fs::read_dir(dir.unwrap().path()).unwrap() {
+                let blob = std::fs::File::options()
+                    .write(true)
+                    .open(blob.unwrap().path())
+                    .unwrap();
+                blob.set_modified(written).unwrap();
+            }
+        }
+
+        let retention = Retention {
+            max_checks: 2,
+            max_age_days: 0,
+        };
+        let report = storage.gc(std::slice::from_ref(&site), &retention).unwrap();
+
+        assert_eq!((report.checks_removed, report.removed), (1, 1));
+        let history = storage.load_history(&site.id).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].hash, check("v2", 0).hash);
+        assert_eq!(storage.get_snapshot(&site.id).unwrap(), "v3");
+    }
+
+    #[test]
+    fn gc_drops_archived_responses_past_the_age_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FileStorage::new(dir.path().to_path_buf(), dir.path().to_path_buf());
+        let site = WatchedSite::new("https://example.com".to_string(), "Ex".to_string(), None);
+
+        storage.archive_response(&site.id, &response(100)).unwrap();
+        storage.archive_response(&site.id, &response(1)).unwrap();
+        storage.record_check(&site.id, &check("v1", 100)).unwrap();
+
+        let report = storage
+            .gc(std::slice::from_ref(&site), &Retention::default())
+            .unwrap();
+
+        assert_eq!((report.checks_removed, report.responses_removed), (1, 1));
+        assert_eq!(storage.load_archive(&site.id).unwrap().len(), 1);
+        assert!(storage.load_history(&site.id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn responses_fetched_together_are_all_archived() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FileStorage::new(dir.path().to_path_buf(), dir.path().to_path_buf());
+        let fetched_at = Utc::now();
+
+        for body in ["<p>one</p>", "<p>two</p>"] {
+            let response = RawResponse {
+                fetched_at,
+                status: 200,
+                headers: Vec::new(),
+                body: body.to_string(),
+                hash: String::new(),
+            };
+            storage.archive_response("a1", &response).unwrap();
+        }
+
+        let mut bodies: Vec<String> = storage
+            .load_archive("a1")
+            .unwrap()
+            .into_iter()
+            .map(|response| response.body)
+            .collect();
+        bodies.sort();
+        assert_eq!(bodies, ["<p>one</p>", "<p>two</p>"]);
+    }
+}
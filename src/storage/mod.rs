@@ -1,83 +1,431 @@
-use crate::config::{AppConfig, WatchedSite};
+mod files;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+use crate::config::{AppConfig, Config, Retention, StorageBackend, WatchedSite};
+use crate::notify::Pending;
+use crate::state::StateStore;
 use anyhow::Result;
-use std::path::PathBuf;
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+use std::path::Path;
+
+pub use files::FileStorage;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStorage;
 
-pub struct Storage {
-    data_dir: PathBuf,
-    config_dir: PathBuf,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckRecord {
+    pub timestamp: DateTime<Utc>,
+    pub changed: bool,
+    pub response_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<f64>,
+    /// Hash of the filtered content; `None` if the check failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
 }
 
-impl Storage {
-    pub fn new(data_dir: PathBuf, config_dir: PathBuf) -> Self {
-        Self {
-            data_dir,
-            config_dir,
-        }
+/// A fetched page as received, kept for sites with `archive_raw`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RawResponse {
+    pub fetched_at: DateTime<Utc>,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    /// Hash of the content extracted from `body` when it was fetched.
+    pub hash: String,
+}
+
+/// Where check history, snapshots, diffs and queued notifications live.
+/// Sites come from config.toml and their runtime state from the state
+/// store with either backend.
+pub trait Storage: Send + Sync {
+    /// Makes `content`, whose SHA-256 is `hash`, the latest snapshot of the
+    /// site. Contents are stored compressed and only once per hash.
+    fn save_snapshot(&self, site_id: &str, hash: &str, content: &str) -> Result<()>;
+
+    /// The latest snapshot, or an empty string if there is none.
+    fn get_snapshot(&self, site_id: &str) -> Result<String>;
+
+    /// Records the diff of a change.
+    fn save_diff(&self, site_id: &str, diff: &str) -> Result<()>;
+
+    /// Returns the diff of the most recent change, if any.
+    fn get_diff(&self, site_id: &str) -> Result<Option<String>>;
+
+    fn record_check(&self, site_id: &str, record: &CheckRecord) -> Result<()>;
+
+    /// All recorded checks of a site, oldest first.
+    fn load_history(&self, site_id: &str) -> Result<Vec<CheckRecord>>;
+
+    /// The latest `limit` checks of a site, oldest first, read without
+    /// going through the older ones.
+    fn recent_history(&self, site_id: &str, limit: usize) -> Result<Vec<CheckRecord>>;
+
+    /// Replaces the check history of a site, e.g. after re-extraction.
+    fn replace_history(&self, site_id: &str, records: &[CheckRecord]) -> Result<()>;
+
+    fn archive_response(&self, site_id: &str, response: &RawResponse) -> Result<()>;
+
+    /// All archived responses of a site, oldest first.
+    fn load_archive(&self, site_id: &str) -> Result<Vec<RawResponse>>;
+
+    /// Appends an alert to the queue of notifier `name`.
+    fn queue_pending(&self, name: &str, alert: &Pending) -> Result<()>;
+
+    /// The queued alerts of notifier `name`, oldest first.
+    fn load_pending(&self, name: &str) -> Result<Vec<Pending>>;
+
+    /// Replaces the oldest `count` queued alerts of notifier `name` with
+    /// `unsent` in one step. Alerts queued since they were loaded stay
+    /// behind `unsent`.
+    fn replace_pending(&self, name: &str, count: usize, unsent: &[Pending]) -> Result<()>;
+
+    /// config.toml with runtime state and group settings filled in, or
+    /// `None` if it cannot be loaded.
+    fn load_config(&self) -> Option<AppConfig>;
+
+    fn load_sites(&self) -> Vec<WatchedSite> {
+        self.load_config()
+            .map(|config| config.sites)
+            .unwrap_or_default()
     }
 
-    pub fn save_snapshot(&self, site_id: &str, content: &str) -> Result<()> {
-        Self::validate_site_id(site_id)?;
+    /// Trims the checks, diffs and archived responses of `sites` to
+    /// `retention`, then removes stored snapshot contents that none of
+    /// `sites` refers to anymore, e.g. those of removed sites or only
+    /// referred to by trimmed checks, and the archived responses of sites
+    /// not in `sites`.
+    fn gc(&self, sites: &[WatchedSite], retention: &Retention) -> Result<GcReport>;
+
+    /// Deletes every snapshot, diff, check, archived response and queued
+    /// alert, before `migrate` moves another backend's data in.
+    fn clear(&self) -> Result<()>;
+
+    /// Saves the check results of `site` to the state store, leaving the
+    /// mute, snooze and last seen time as they are. config.toml is never
+    /// written here.
+    fn save_state(&self, site: &WatchedSite) -> Result<()> {
+        StateStore::new(self.data_dir()).save_checks(site)
+    }
+
+    fn data_dir(&self) -> &Path;
+}
+
+/// What `migrate` moved over.
+#[derive(Debug, Default)]
+pub struct MigrateReport {
+    pub from: StorageBackend,
+    pub sites: usize,
+    pub checks: usize,
+    pub responses: usize,
+    pub alerts: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct GcReport {
+    /// Checks past the retention limits.
+    pub checks_removed: usize,
+    /// Archived responses past the retention limits.
+    pub responses_removed: usize,
+    pub removed: usize,
+    /// Sites whose archived responses were deleted.
+    pub archives_removed: usize,
+    pub freed_bytes: u64,
+}
+
+/// The oldest time `retention` keeps anything from, if it limits age.
+fn retention_cutoff(retention: &Retention, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    (retention.max_age_days > 0)
+        .then(|| now - chrono::Duration::days(i64::from(retention.max_age_days)))
+}
+
+/// The part of `history`, oldest first, that `retention` keeps.
+fn retained<'a>(
+    history: &'a [CheckRecord],
+    retention: &Retention,
+    now: DateTime<Utc>,
+) -> &'a [CheckRecord] {
+    let mut start = 0;
+    if retention.max_checks > 0 {
+        start = history.len().saturating_sub(retention.max_checks);
+    }
+    if let Some(cutoff) = retention_cutoff(retention, now) {
+        start = start.max(history.partition_point(|record| record.timestamp < cutoff));
+    }
+    &history[start..]
+}
+
+fn compress(content: &str) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(content.as_bytes())?;
+    Ok(encoder.finish()?)
+}
+
+fn decompress(blob: &[u8]) -> Result<String> {
+    let mut content = String::new();
+    GzDecoder::new(blob).read_to_string(&mut content)?;
+    Ok(content)
+}
+
+/// Names the backend holding the data in the data directory, so a switch
+/// of `storage` in config.toml is noticed instead of every site starting
+/// over with an empty snapshot.
+const BACKEND_FILE: &str = "storage-backend";
+
+/// Opens the storage backend selected in `config`. Fails if the data is
+/// still in the other backend; `migrate` moves it over.
+pub fn open(config: &Config) -> Result<Box<dyn Storage>> {
+    let selected = config.app_config.storage;
 
-        let snapshot_dir = self.data_dir.join("snapshots");
-        std::fs::create_dir_all(&snapshot_dir)?;
+    match active_backend(&config.data_dir)? {
+        Some(active) if active != selected => anyhow::bail!(
+            "config.toml selects storage = \"{}\" but the data is in \"{}\"; \
+             run `rnot storage migrate` to move it over",
+            selected.name(),
+            active.name()
+        ),
+        Some(_) => {}
+        None => set_active_backend(&config.data_dir, selected)?,
+    }
+
+    open_backend(config, selected)
+}
 
-        let snapshot_file = snapshot_dir.join(format!("{}.txt", site_id));
-        std::fs::write(&snapshot_file, content)?;
+/// Copies the data of the backend last used to the one selected in
+/// `config`, replacing anything that one kept from before an earlier
+/// switch. Only the latest snapshot of each site is copied. The old data
+/// is left in place. Returns `None` if the selected backend already holds
+/// the data.
+pub fn migrate(config: &Config) -> Result<Option<MigrateReport>> {
+    let selected = config.app_config.storage;
+    let from = match active_backend(&config.data_dir)? {
+        Some(active) if active != selected => active,
+        _ => return Ok(None),
+    };
 
-        Ok(())
+    let mut queues: Vec<&str> = config
+        .app_config
+        .notifiers
+        .iter()
+        .map(|n| n.name())
+        .collect();
+    if !queues.contains(&"telegram") {
+        queues.push("telegram");
     }
 
-    pub fn get_snapshot(&self, site_id: &str) -> Result<String> {
-        Self::validate_site_id(site_id)?;
+    let source = open_backend(config, from)?;
+    let target = open_backend(config, selected)?;
+    target.clear()?;
+    let report = MigrateReport {
+        from,
+        ..copy_data(&*source, &*target, &config.app_config.sites, &queues)?
+    };
 
-        let snapshot_file = self
-            .data_dir
-            .join("snapshots")
-            .join(format!("{}.txt", site_id));
+    set_active_backend(&config.data_dir, selected)?;
+    Ok(Some(report))
+}
+
+fn copy_data(
+    source: &dyn Storage,
+    target: &dyn Storage,
+    sites: &[WatchedSite],
+    queues: &[&str],
+) -> Result<MigrateReport> {
+    let mut report = MigrateReport::default();
+
+    for site in sites {
+        let history = source.load_history(&site.id)?;
+        target.replace_history(&site.id, &history)?;
+        report.checks += history.len();
 
-        if snapshot_file.exists() {
-            Ok(std::fs::read_to_string(&snapshot_file)?)
-        } else {
-            Ok(String::new())
+        let snapshot = source.get_snapshot(&site.id)?;
+        if !snapshot.is_empty() {
+            let hash = hex::encode(Sha256::digest(snapshot.as_bytes()));
+            target.save_snapshot(&site.id, &hash, &snapshot)?;
         }
+        if let Some(diff) = source.get_diff(&site.id)? {
+            target.save_diff(&site.id, &diff)?;
+        }
+
+        for response in source.load_archive(&site.id)? {
+            target.archive_response(&site.id, &response)?;
+            report.responses += 1;
+        }
+
+        report.sites += 1;
     }
 
-    fn validate_site_id(site_id: &str) -> Result<()> {
-        if site_id.is_empty() || site_id.len() > 64 {
-            anyhow::bail!("Invalid site ID length");
+    for name in queues {
+        for alert in source.load_pending(name)? {
+            target.queue_pending(name, &alert)?;
+            report.alerts += 1;
         }
-        if !site_id.chars().all(|c| c.is_ascii_alphanumeric()) {
-            anyhow::bail!("Site ID contains invalid characters");
+    }
+
+    Ok(report)
+}
+
+/// The backend holding the data: the one recorded by `open`, or for data
+/// directories from before it recorded one, the only one with any data.
+fn active_backend(data_dir: &Path) -> Result<Option<StorageBackend>> {
+    let backend_file = data_dir.join(BACKEND_FILE);
+
+    if backend_file.exists() {
+        let name = std::fs::read_to_string(&backend_file)?;
+        let backend = [StorageBackend::Files, StorageBackend::Sqlite]
+            .into_iter()
+            .find(|backend| backend.name() == name.trim())
+            .ok_or_else(|| {
+                anyhow::anyhow!("Unknown storage backend in {}", backend_file.display())
+            })?;
+        return Ok(Some(backend));
+    }
+
+    let has_files = ["snapshots", "history"]
+        .iter()
+        .any(|dir| data_dir.join(dir).exists());
+    let has_database = data_dir.join("rnot.db").exists();
+
+    Ok(match (has_files, has_database) {
+        (true, false) => Some(StorageBackend::Files),
+        (false, true) => Some(StorageBackend::Sqlite),
+        // Nothing to lose, or no telling which is current
+        _ => None,
+    })
+}
+
+fn set_active_backend(data_dir: &Path, backend: StorageBackend) -> Result<()> {
+    std::fs::create_dir_all(data_dir)?;
+    std::fs::write(data_dir.join(BACKEND_FILE), backend.name())?;
+    Ok(())
+}
+
+fn open_backend(config: &Config, backend: StorageBackend) -> Result<Box<dyn Storage>> {
+    let data_dir = config.data_dir.clone();
+    let config_dir = config.config_dir.clone();
+
+    match backend {
+        StorageBackend::Files => Ok(Box::new(FileStorage::new(data_dir, config_dir))),
+        #[cfg(feature = "sqlite")]
+        StorageBackend::Sqlite => Ok(Box::new(SqliteStorage::open(data_dir, config_dir)?)),
+        #[cfg(not(feature = "sqlite"))]
+        StorageBackend::Sqlite => {
+            anyhow::bail!("storage = \"sqlite\" needs rnot built with the `sqlite` feature")
         }
-        Ok(())
     }
+}
 
-    pub fn load_sites(&self) -> Vec<WatchedSite> {
-        let config_file = self.config_dir.join("config.toml");
+/// config.toml with each site's runtime state and group settings filled
+/// in.
+fn read_config(config_dir: &Path, data_dir: &Path) -> Option<AppConfig> {
+    let config_file = config_dir.join("config.toml");
 
-        if config_file.exists() {
-            if let Ok(content) = std::fs::read_to_string(&config_file) {
-                if let Ok(config) = toml::from_str::<AppConfig>(&content) {
-                    return config.sites;
+    if config_file.exists() {
+        if let Ok(content) = std::fs::read_to_string(&config_file) {
+            if let Ok(mut config) = crate::config::parse(&content) {
+                StateStore::new(data_dir).apply_all(&mut config.sites);
+                match config.apply_groups() {
+                    Ok(()) => return Some(config),
+                    Err(e) => tracing::warn!("Ignoring config.toml: {}", e),
                 }
             }
         }
-
-        Vec::new()
     }
 
-    pub fn update_site(&self, site: &WatchedSite) -> Result<()> {
-        let config_file = self.config_dir.join("config.toml");
-        let content = std::fs::read_to_string(&config_file)?;
-        let mut config: AppConfig = toml::from_str(&content)?;
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        if let Some(existing) = config.sites.iter_mut().find(|s| s.id == site.id) {
-            *existing = site.clone();
+    fn check(hash: &str) -> CheckRecord {
+        CheckRecord {
+            timestamp: Utc::now(),
+            changed: true,
+            response_ms: 120,
+            value: None,
+            hash: Some(hash.to_string()),
+            error: None,
         }
+    }
+
+    #[test]
+    fn the_backend_with_data_is_active() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(active_backend(dir.path()).unwrap(), None);
+
+        std::fs::create_dir(dir.path().join("history")).unwrap();
+        assert_eq!(
+            active_backend(dir.path()).unwrap(),
+            Some(StorageBackend::Files)
+        );
+
+        // Both have data: no telling which is current
+        std::fs::write(dir.path().join("rnot.db"), "").unwrap();
+        assert_eq!(active_backend(dir.path()).unwrap(), None);
+
+        set_active_backend(dir.path(), StorageBackend::Sqlite).unwrap();
+        assert_eq!(
+            active_backend(dir.path()).unwrap(),
+            Some(StorageBackend::Sqlite)
+        );
+    }
+
+    #[test]
+    fn copy_data_moves_everything_but_older_snapshots() {
+        let from_dir = tempfile::tempdir().unwrap();
+        let to_dir = tempfile::tempdir().unwrap();
+        let source = FileStorage::new(from_dir.path().to_path_buf(), from_dir.path().to_path_buf());
+        let target = FileStorage::new(to_dir.path().to_path_buf(), to_dir.path().to_path_buf());
+        let site = WatchedSite::new(
+            "https://example.com".to_string(),
+            "Example".to_string(),
+            None,
+        );
+
+        let old_hash = hex::encode(Sha256::digest(b"old"));
+        let new_hash = hex::encode(Sha256::digest(b"new"));
+        source.save_snapshot(&site.id, &old_hash, "old").unwrap();
+        source.save_snapshot(&site.id, &new_hash, "new").unwrap();
+        source.save_diff(&site.id, "- old\n+ new").unwrap();
+        source.record_check(&site.id, &check(&old_hash)).unwrap();
+        source.record_check(&site.id, &check(&new_hash)).unwrap();
+        source
+            .queue_pending("mail", &Pending::Recovery { site: site.clone() })
+            .unwrap();
+
+        // Left over from before an earlier switch
+        target.record_check(&site.id, &check(&old_hash)).unwrap();
+        target
+            .queue_pending("mail", &Pending::Recovery { site: site.clone() })
+            .unwrap();
+        target.clear().unwrap();
 
-        let updated = toml::to_string_pretty(&config)?;
-        std::fs::write(&config_file, updated)?;
+        let report = copy_data(
+            &source,
+            &target,
+            std::slice::from_ref(&site),
+            &["mail", "telegram"],
+        )
+        .unwrap();
 
-        Ok(())
+        assert_eq!((report.sites, report.checks, report.alerts), (1, 2, 1));
+        assert_eq!(target.get_snapshot(&site.id).unwrap(), "new");
+        assert_eq!(
+            target.get_diff(&site.id).unwrap().as_deref(),
+            Some("- old\n+ new")
+        );
+        assert_eq!(target.load_history(&site.id).unwrap().len(), 2);
+        assert_eq!(target.load_pending("mail").unwrap().len(), 1);
     }
 }
@@ -0,0 +1,530 @@
+use super::{
+    compress, decompress, read_config, retention_cutoff, CheckRecord, GcReport, RawResponse,
+    Storage,
+};
+use crate::config::{AppConfig, Retention, WatchedSite};
+use crate::notify::Pending;
+use anyhow::Result;
+use chrono::Utc;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS contents (
+        hash TEXT PRIMARY KEY,
+        content BLOB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS snapshots (
+        id INTEGER PRIMARY KEY,
+        site_id TEXT NOT NULL,
+        taken_at TEXT NOT NULL,
+        hash TEXT NOT NULL REFERENCES contents(hash)
+    );
+    CREATE INDEX IF NOT EXISTS snapshots_site ON snapshots(site_id, id);
+    CREATE TABLE IF NOT EXISTS checks (
+        id INTEGER PRIMARY KEY,
+        site_id TEXT NOT NULL,
+        checked_at TEXT NOT NULL,
+        changed INTEGER NOT NULL,
+        response_ms INTEGER NOT NULL,
+        value REAL,
+        hash TEXT,
+        error TEXT
+    );
+    CREATE INDEX IF NOT EXISTS checks_site ON checks(site_id, id);
+    CREATE TABLE IF NOT EXISTS changes (
+        id INTEGER PRIMARY KEY,
+        site_id TEXT NOT NULL,
+        detected_at TEXT NOT NULL,
+        diff TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS changes_site ON changes(site_id, id);
+    CREATE TABLE IF NOT EXISTS responses (
+        id INTEGER PRIMARY KEY,
+        site_id TEXT NOT NULL,
+        fetched_at TEXT NOT NULL,
+        status INTEGER NOT NULL,
+        headers TEXT NOT NULL,
+        body BLOB NOT NULL,
+        hash TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS responses_site ON responses(site_id, id);
+    CREATE TABLE IF NOT EXISTS pending (
+        id INTEGER PRIMARY KEY,
+        notifier TEXT NOT NULL,
+        change TEXT NOT NULL
+    );
+";
+
+/// A single SQLite database, `rnot.db` in the data directory. Every check,
+/// change and snapshot is kept; snapshot contents are stored gzipped and
+/// once per distinct hash.
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+    data_dir: PathBuf,
+    config_dir: PathBuf,
+}
+
+impl SqliteStorage {
+    pub fn open(data_dir: PathBuf, config_dir: PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(&data_dir)?;
+
+        let conn = Connection::open(data_dir.join("rnot.db"))?;
+        // The daemon, the TUI and one-off commands may all have it open
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA)?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+            data_dir,
+            config_dir,
+        })
+    }
+
+    fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+        // A panic while holding the lock cannot leave SQLite inconsistent
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Writes a consistent copy of the database at `db` to `dest`, which
+    /// must not exist yet, while other processes may be writing to it.
+    pub fn copy_database(db: &Path, dest: &Path) -> Result<()> {
+        let conn = Connection::open_with_flags(db, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.execute("VACUUM INTO ?1", params![dest.to_string_lossy()])?;
+        Ok(())
+    }
+}
+
+fn check_record(row: &rusqlite::Row) -> rusqlite::Result<CheckRecord> {
+    Ok(CheckRecord {
+        timestamp: row.get(0)?,
+        changed: row.get(1)?,
+        response_ms: row.get::<_, i64>(2)? as u64,
+        value: row.get(3)?,
+        hash: row.get(4)?,
+        error: row.get(5)?,
+    })
+}
+
+impl Storage for SqliteStorage {
+    fn save_snapshot(&self, site_id: &str, hash: &str, content: &str) -> Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT OR IGNORE INTO contents (hash, content) VALUES (?1, ?2)",
+            params![hash, compress(content)?],
+        )?;
+        tx.execute(
+            "INSERT INTO snapshots (site_id, taken_at, hash) VALUES (?1, ?2, ?3)",
+            params![site_id, Utc::now(), hash],
+        )?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn get_snapshot(&self, site_id: &str) -> Result<String> {
+        let blob: Option<Vec<u8>> = self
+            .conn()
+            .query_row(
+                "SELECT c.content FROM snapshots s JOIN contents c ON c.hash = s.hash
+                 WHERE s.site_id = ?1 ORDER BY s.id DESC LIMIT 1",
+                params![site_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        match blob {
+            Some(blob) => decompress(&blob),
+            None => Ok(String::new()),
+        }
+    }
+
+    fn save_diff(&self, site_id: &str, diff: &str) -> Result<()> {
+        self.conn().execute(
+            "INSERT INTO changes (site_id, detected_at, diff) VALUES (?1, ?2, ?3)",
+            params![site_id, Utc::now(), diff],
+        )?;
+        Ok(())
+    }
+
+    fn get_diff(&self, site_id: &str) -> Result<Option<String>> {
+        Ok(self
+            .conn()
+            .query_row(
+                "SELECT diff FROM changes WHERE site_id = ?1 ORDER BY id DESC LIMIT 1",
+                params![site_id],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn record_check(&self, site_id: &str, record: &CheckRecord) -> Result<()> {
+        self.conn().execute(
+            "INSERT INTO checks (site_id, checked_at, changed, response_ms, value, hash, error)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                site_id,
+                record.timestamp,
+                record.changed,
+                record.response_ms as i64,
+                record.value,
+                record.hash,
+                record.error,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn load_history(&self, site_id: &str) -> Result<Vec<CheckRecord>> {
+        let conn = self.conn();
+        let mut statement = conn.prepare(
+            "SELECT checked_at, changed, response_ms, value, hash, error
+             FROM checks WHERE site_id = ?1 ORDER BY id",
+        )?;
+
+        let records = statement
+            .query_map(params![site_id], check_record)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(records)
+    }
+
+    fn recent_history(&self, site_id: &str, limit: usize) -> Result<Vec<CheckRecord>> {
+        let conn = self.conn();
+        let mut statement = conn.prepare(
+            "SELECT checked_at, changed, response_ms, value, hash, error
+             FROM checks WHERE site_id = ?1 ORDER BY id DESC LIMIT ?2",
+        )?;
+
+        let mut records = statement
+            .query_map(params![site_id, limit as i64], check_record)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        records.reverse();
+
+        Ok(records)
+    }
+
+    fn replace_history(&self, site_id: &str, records: &[CheckRecord]) -> Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM checks WHERE site_id = ?1", params![site_id])?;
+        for record in records {
+            tx.execute(
+                "INSERT INTO checks (site_id, checked_at, changed, response_ms, value, hash, error)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    site_id,
+                    record.timestamp,
+                    record.changed,
+                    record.response_ms as i64,
+                    record.value,
+                    record.hash,
+                    record.error,
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn archive_response(&self, site_id: &str, response: &RawResponse) -> Result<()> {
+        self.conn().execute(
+            "INSERT INTO responses (site_id, fetched_at, status, headers, body, hash)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                site_id,
+                response.fetched_at,
+                response.status,
+                serde_json::to_string(&response.headers)?,
+                compress(&response.body)?,
+                response.hash,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn load_archive(&self, site_id: &str) -> Result<Vec<RawResponse>> {
+        let conn = self.conn();
+        let mut statement = conn.prepare(
+            "SELECT fetched_at, status, headers, body, hash
+             FROM responses WHERE site_id = ?1 ORDER BY id",
+        )?;
+
+        let rows = statement
+            .query_map(params![site_id], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Vec<u8>>(3)?,
+                    row.get(4)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        rows.into_iter()
+            .map(|(fetched_at, status, headers, body, hash)| {
+                Ok(RawResponse {
+                    fetched_at,
+                    status,
+                    headers: serde_json::from_str(&headers)?,
+                    body: decompress(&body)?,
+                    hash,
+                })
+            })
+            .collect()
+    }
+
+    fn queue_pending(&self, name: &str, alert: &Pending) -> Result<()> {
+        self.conn().execute(
+            "INSERT INTO pending (notifier, change) VALUES (?1, ?2)",
+            params![name, serde_json::to_string(alert)?],
+        )?;
+        Ok(())
+    }
+
+    fn load_pending(&self, name: &str) -> Result<Vec<Pending>> {
+        let conn = self.conn();
+        let mut statement =
+            conn.prepare("SELECT change FROM pending WHERE notifier = ?1 ORDER BY id")?;
+
+        let alerts = statement
+            .query_map(params![name], |row| row.get::<_, String>(0))?
+            .filter_map(|json| serde_json::from_str(&json.ok()?).ok())
+            .collect();
+
+        Ok(alerts)
+    }
+
+    fn replace_pending(&self, name: &str, count: usize, unsent: &[Pending]) -> Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        let ids = tx
+            .prepare("SELECT id FROM pending WHERE notifier = ?1 ORDER BY id LIMIT ?2")?
+            .query_map(params![name, count as i64], |row| row.get::<_, i64>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for id in &ids {
+            tx.execute("DELETE FROM pending WHERE id = ?1", params![id])?;
+        }
+        // Reusing the freed IDs keeps the unsent alerts ahead of any queued
+        // since
+        for (i, alert) in unsent.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO pending (id, notifier, change) VALUES (?1, ?2, ?3)",
+                params![ids.get(i), name, serde_json::to_string(alert)?],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn load_config(&self) -> Option<AppConfig> {
+        read_config(&self.config_dir, &self.data_dir)
+    }
+
+    fn gc(&self, sites: &[WatchedSite], retention: &Retention) -> Result<GcReport> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+
+        let mut checks_removed = 0;
+        if retention.max_checks > 0 {
+            for site in sites {
+                checks_removed += tx.execute(
+                    "DELETE FROM checks WHERE site_id = ?1 AND id NOT IN
+                     (SELECT id FROM checks WHERE site_id = ?1 ORDER BY id DESC LIMIT ?2)",
+                    params![site.id, retention.max_checks as i64],
+                )?;
+            }
+        }
+
+        let (mut responses_removed, mut response_bytes) = (0, 0);
+        if let Some(cutoff) = retention_cutoff(retention, Utc::now()) {
+            checks_removed +=
+                tx.execute("DELETE FROM checks WHERE checked_at < ?1", params![cutoff])?;
+            // The latest diff of a site is shown however old it is
+            tx.execute(
+                "DELETE FROM changes WHERE detected_at < ?1
+                 AND id NOT IN (SELECT MAX(id) FROM changes GROUP BY site_id)",
+                params![cutoff],
+            )?;
+            (responses_removed, response_bytes) = tx.query_row(
+                "SELECT COUNT(*), COALESCE(SUM(LENGTH(body)), 0)
+                 FROM responses WHERE fetched_at < ?1",
+                params![cutoff],
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)),
+            )?;
+            tx.execute(
+                "DELETE FROM responses WHERE fetched_at < ?1",
+                params![cutoff],
+            )?;
+        }
+
+        // Older versions stay as long as the kept checks mention them
+        tx.execute(
+            "DELETE FROM snapshots
+             WHERE id NOT IN (SELECT MAX(id) FROM snapshots GROUP BY site_id)
+             AND hash NOT IN (SELECT hash FROM checks
+                              WHERE site_id = snapshots.site_id AND hash IS NOT NULL)",
+            [],
+        )?;
+
+        // Snapshots of removed sites go
+        tx.execute_batch("CREATE TEMP TABLE live_sites (site_id TEXT PRIMARY KEY)")?;
+        for site in sites {
+            tx.execute(
+                "INSERT OR IGNORE INTO live_sites (site_id) VALUES (?1)",
+                params![site.id],
+            )?;
+        }
+        tx.execute(
+            "DELETE FROM snapshots WHERE site_id NOT IN (SELECT site_id FROM live_sites)",
+            [],
+        )?;
+        let archived = "FROM responses WHERE site_id NOT IN (SELECT site_id FROM live_sites)";
+        let (archives_removed, archive_bytes): (i64, i64) = tx.query_row(
+            &format!(
+                "SELECT COUNT(DISTINCT site_id), COALESCE(SUM(LENGTH(body)), 0) {}",
+                archived
+            ),
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        tx.execute(&format!("DELETE {}", archived), [])?;
+        tx.execute_batch("DROP TABLE live_sites")?;
+
+        let orphaned = "FROM contents WHERE hash NOT IN (SELECT hash FROM snapshots)";
+        let (removed, freed_bytes): (i64, i64) = tx.query_row(
+            &format!(
+                "SELECT COUNT(*), COALESCE(SUM(LENGTH(content)), 0) {}",
+                orphaned
+            ),
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        tx.execute(&format!("DELETE {}", orphaned), [])?;
+        tx.commit()?;
+
+        Ok(GcReport {
+            checks_removed,
+            responses_removed: responses_removed as usize,
+            removed: removed as usize,
+            archives_removed: archives_removed as usize,
+            freed_bytes: (freed_bytes + archive_bytes + response_bytes) as u64,
+        })
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.conn().execute_batch(
+            "BEGIN;
+             DELETE FROM snapshots;
+             DELETE FROM contents;
+             DELETE FROM checks;
+             DELETE FROM changes;
+             DELETE FROM responses;
+             DELETE FROM pending;
+             COMMIT;",
+        )?;
+        Ok(())
+    }
+
+    fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(hash: &str, days_ago: i64) -> CheckRecord {
+        CheckRecord {
+            timestamp: Utc::now() - chrono::Duration::days(days_ago),
+            changed: true,
+            response_ms: 80,
+            value: None,
+            hash: Some(hash.to_string()),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn unsent_alerts_stay_ahead_of_those_queued_since() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage =
+            SqliteStorage::open(dir.path().to_path_buf(), dir.path().to_path_buf()).unwrap();
+        let site = WatchedSite::new("https://example.com".into(), "Example".into(), None);
+        let error = |error: &str| Pending::Error {
+            site: site.clone(),
+            error: error.to_string(),
+        };
+
+        for name in ["a", "b", "c"] {
+            storage.queue_pending("mail", &error(name)).unwrap();
+        }
+        let loaded = storage.load_pending("mail").unwrap();
+        storage.queue_pending("mail", &error("d")).unwrap();
+        storage
+            .replace_pending("mail", loaded.len(), &loaded[1..])
+            .unwrap();
+
+        let errors: Vec<_> = storage
+            .load_pending("mail")
+            .unwrap()
+            .into_iter()
+            .map(|alert| match alert {
+                Pending::Error { error, .. } => error,
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!(errors, ["b", "c", "d"]);
+    }
+
+    #[test]
+    fn recent_history_is_the_latest_checks_oldest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage =
+            SqliteStorage::open(dir.path().to_path_buf(), dir.path().to_path_buf()).unwrap();
+
+        for hash in ["h1", "h2", "h3"] {
+            storage.record_check("a1", &check(hash, 0)).unwrap();
+        }
+
+        let recent = storage.recent_history("a1", 2).unwrap();
+        let hashes: Vec<_> = recent.iter().map(|r| r.hash.as_deref()).collect();
+        assert_eq!(hashes, [Some("h2"), Some("h3")]);
+    }
+
+    #[test]
+    fn gc_trims_checks_and_the_snapshot_versions_only_they_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage =
+            SqliteStorage::open(dir.path().to_path_buf(), dir.path().to_path_buf()).unwrap();
+        let site = WatchedSite::new("https://example.com".to_string(), "Ex".to_string(), None);
+
+        for (hash, days_ago) in [("h1", 200), ("h2", 2), ("h3", 1)] {
+            storage.save_snapshot(&site.id, hash, hash).unwrap();
+            storage
+                .record_check(&site.id, &check(hash, days_ago))
+                .unwrap();
+            storage.save_diff(&site.id, hash).unwrap();
+        }
+
+        let retention = Retention {
+            max_checks: 1,
+            max_age_days: 90,
+        };
+        let report = storage.gc(std::slice::from_ref(&site), &retention).unwrap();
+
+        assert_eq!((report.checks_removed, report.removed), (2, 2));
+        let history = storage.load_history(&site.id).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].hash.as_deref(), Some("h3"));
+        assert_eq!(storage.get_snapshot(&site.id).unwrap(), "h3");
+        assert_eq!(storage.get_diff(&site.id).unwrap().as_deref(), Some("h3"));
+    }
+}
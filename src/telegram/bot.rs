@@ -0,0 +1,443 @@
+use super::{SiteAction, TelegramClient, TelegramMessage};
+use crate::config::{Config, WatchedSite};
+use crate::monitor::Monitor;
+use crate::storage::Storage;
+use anyhow::Result;
+use chrono::Utc;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{error, info, warn};
+
+const POLL_TIMEOUT_SECS: u64 = 30;
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+const HELP: &str = "Commands:
+/list - list watched sites
+/add <url> [name] - watch a new site
+/remove <id> - stop watching a site
+/pause <id> - pause checks for a site
+/resume <id> - resume checks for a site
+/check <id> - check a site now
+/diff <id> - show the last change of a site
+/unmute <id> - resume change alerts for a muted or snoozed site";
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Help,
+    List,
+    Add { url: String, name: Option<String> },
+    Remove(String),
+    Pause(String),
+    Resume(String),
+    Check(String),
+    Diff(String),
+    Unmute(String),
+}
+
+impl Command {
+    /// Parses a message like `/add https://example.com Example`. Returns
+    /// `None` for text that is not a command and `Err` with a usage hint for
+    /// malformed ones.
+    fn parse(text: &str) -> Option<std::result::Result<Self, String>> {
+        let mut words = text.split_whitespace();
+        let command = words.next()?.strip_prefix('/')?;
+        // Commands in groups may be addressed as /list@SomeBot
+        let command = command.split('@').next().unwrap_or(command);
+        let arg = words.next().map(String::from);
+        let rest: Vec<&str> = words.collect();
+
+        let site_arg = |arg: Option<String>, usage: &str| arg.ok_or(format!("Usage: {}", usage));
+
+        Some(match command {
+            "start" | "help" => Ok(Self::Help),
+            "list" => Ok(Self::List),
+            "add" => site_arg(arg, "/add <url> [name]").map(|url| Self::Add {
+                url,
+                name: (!rest.is_empty()).then(|| rest.join(" ")),
+            }),
+            "remove" => site_arg(arg, "/remove <id>").map(Self::Remove),
+            "pause" => site_arg(arg, "/pause <id>").map(Self::Pause),
+            "resume" => site_arg(arg, "/resume <id>").map(Self::Resume),
+            "check" => site_arg(arg, "/check <id>").map(Self::Check),
+            "diff" => site_arg(arg, "/diff <id>").map(Self::Diff),
+            "unmute" => site_arg(arg, "/unmute <id>").map(Self::Unmute),
+            _ => Err(format!("Unknown command /{}\n\n{}", command, HELP)),
+        })
+    }
+}
+
+/// Long-polls Telegram for commands from the configured chats and replies
+/// with the results. Messages from any other chat are ignored.
+pub struct Bot {
+    client: TelegramClient,
+    chats: Vec<String>,
+    storage: Box<dyn Storage>,
+}
+
+impl Bot {
+    pub fn new(token: String, chats: Vec<String>, storage: Box<dyn Storage>) -> Self {
+        Self {
+            client: TelegramClient::new(Some(token), None),
+            chats,
+            storage,
+        }
+    }
+
+    fn is_authorized(&self, chat_id: i64) -> bool {
+        self.chats.contains(&chat_id.to_string())
+    }
+
+    pub async fn run(
+        &self,
+        config: &mut Config,
+        monitor: &Monitor,
+        mut shutdown_rx: broadcast::Receiver<()>,
+    ) {
+        // Commands and button presses sent while the bot was not running
+        // are dropped, not acted on late. Offset -1 confirms all but the
+        // latest update, which is skipped too.
+        let mut offset = Some(-1);
+        let mut draining = true;
+
+        loop {
+            let timeout_secs = if draining { 0 } else { POLL_TIMEOUT_SECS };
+            let updates = tokio::select! {
+                updates = self.client.poll_updates(offset, timeout_secs) => updates,
+                _ = shutdown_rx.recv() => break,
+            };
+
+            let updates = match updates {
+                Ok(updates) => updates,
+                Err(e) => {
+                    error!("Failed to poll Telegram updates: {}", e);
+                    tokio::time::sleep(RETRY_DELAY).await;
+                    continue;
+                }
+            };
+
+            for update in updates {
+                if let Some(id) = update["update_id"].as_i64() {
+                    offset = Some(id + 1);
+                }
+                if draining {
+                    info!("Skipping Telegram updates sent before startup");
+                } else {
+                    self.handle_update(&update, config, monitor).await;
+                }
+            }
+
+            if draining {
+                draining = false;
+                if offset == Some(-1) {
+                    offset = None;
+                }
+            }
+        }
+    }
+
+    async fn handle_update(&self, update: &Value, config: &mut Config, monitor: &Monitor) {
+        if update["callback_query"].is_object() {
+            self.handle_callback(&update["callback_query"], config)
+                .await;
+            return;
+        }
+
+        let message = &update["message"];
+        let (Some(chat_id), Some(text)) =
+            (message["chat"]["id"].as_i64(), message["text"].as_str())
+        else {
+            return;
+        };
+
+        if !self.is_authorized(chat_id) {
+            warn!(
+                "Ignoring Telegram message from unauthorized chat {}",
+                chat_id
+            );
+            return;
+        }
+
+        let chat = self
+            .client
+            .for_chat(chat_id.to_string(), message["message_thread_id"].as_i64());
+        let answer = match Command::parse(text) {
+            None => return,
+            Some(Err(usage)) => usage,
+            Some(Ok(command)) => {
+                info!("Telegram command: {}", text);
+                self.execute(command, config, monitor)
+                    .await
+                    .unwrap_or_else(|e| format!("Error: {}", e))
+            }
+        };
+
+        reply(&chat, &answer).await;
+    }
+
+    /// Handles a press of one of the buttons under a change alert.
+    async fn handle_callback(&self, query: &Value, config: &mut Config) {
+        let Some(query_id) = query["id"].as_str() else {
+            return;
+        };
+        let message = &query["message"];
+        let Some(chat_id) = message["chat"]["id"]
+            .as_i64()
+            .filter(|id| self.is_authorized(*id))
+        else {
+            warn!("Ignoring Telegram button press from unauthorized chat");
+            return;
+        };
+        let chat = self
+            .client
+            .for_chat(chat_id.to_string(), message["message_thread_id"].as_i64());
+
+        let Some((action, site_id)) = query["data"].as_str().and_then(SiteAction::parse) else {
+            return;
+        };
+        info!("Telegram action {:?} on {}", action, site_id);
+
+        let toast = self
+            .apply_action(action, site_id, config, &chat)
+            .await
+            .unwrap_or_else(|e| format!("Error: {}", e));
+
+        if let Err(e) = self.client.answer_callback_query(query_id, &toast).await {
+            error!("Failed to answer Telegram button press: {}", e);
+        }
+
+        if action == SiteAction::Seen {
+            if let Some(message_id) = message["message_id"].as_i64() {
+                if let Err(e) = self.client.clear_keyboard(chat_id, message_id).await {
+                    error!("Failed to clear Telegram keyboard: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Applies `action` to the site and returns a short confirmation.
+    async fn apply_action(
+        &self,
+        action: SiteAction,
+        site_id: &str,
+        config: &mut Config,
+        chat: &TelegramClient,
+    ) -> Result<String> {
+        config.reload()?;
+        let Some(mut site) = find_site(config, site_id) else {
+            return Ok("Site no longer watched".to_string());
+        };
+
+        match action {
+            SiteAction::Diff => {
+                reply(chat, &self.last_diff(&site)?).await;
+                return Ok("Sent full diff".to_string());
+            }
+            SiteAction::Snooze => {
+                site.snoozed_until = Some(Utc::now() + chrono::Duration::days(1));
+                config.save_alert_state(&site)?;
+            }
+            SiteAction::Mute => {
+                site.muted = true;
+                config.save_alert_state(&site)?;
+            }
+            SiteAction::Seen => {
+                site.last_seen = Some(Utc::now());
+                config.save_alert_state(&site)?;
+            }
+        }
+
+        Ok(match action {
+            SiteAction::Snooze => format!("{} snoozed for 1 day", site.name),
+            SiteAction::Mute => format!("{} muted, /unmute {} to undo", site.name, site.id),
+            _ => format!("{} marked as seen", site.name),
+        })
+    }
+
+    fn last_diff(&self, site: &WatchedSite) -> Result<String> {
+        match self.storage.get_diff(&site.id)? {
+            Some(diff) => {
+                let time = site
+                    .last_change
+                    .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+                    .unwrap_or_else(|| "unknown".to_string());
+                Ok(format!(
+                    "Last change on {} ({}):\n\n{}",
+                    site.name, time, diff
+                ))
+            }
+            None => Ok(format!("No changes recorded for {}.", site.name)),
+        }
+    }
+
+    async fn execute(
+        &self,
+        command: Command,
+        config: &mut Config,
+        monitor: &Monitor,
+    ) -> Result<String> {
+        // The monitor persists check results to the state store and other
+        // commands may have edited config.toml, so start from both rather
+        // than our in-memory copy before changing anything
+        config.reload()?;
+
+        match command {
+            Command::Help => Ok(HELP.to_string()),
+            Command::List => {
+                let sites = &config.app_config.sites;
+                if sites.is_empty() {
+                    return Ok("No sites being monitored.".to_string());
+                }
+
+                let lines: Vec<String> = sites
+                    .iter()
+                    .map(|site| {
+                        let status = if !site.enabled {
+                            "⏸"
+                        } else if site.last_error.is_some() {
+                            "⚠"
+                        } else {
+                            "✓"
+                        };
+                        let muted = if site.is_silenced() { " 🔕" } else { "" };
+                        format!(
+                            "{} {}{} [{}]\n{}",
+                            status, site.name, muted, site.id, site.url
+                        )
+                    })
+                    .collect();
+                Ok(lines.join("\n\n"))
+            }
+            Command::Add { url, name } => {
+                let name = name.unwrap_or_else(|| {
+                    url::Url::parse(&url)
+                        .ok()
+                        .and_then(|u| u.host_str().map(|s| s.to_string()))
+                        .unwrap_or_else(|| url.clone())
+                });
+                let id = config.add_site(url, name.clone(), None)?;
+                Ok(format!("Added site '{}' with ID: {}", name, id))
+            }
+            Command::Remove(id) => {
+                if config.remove_site(&id)? {
+                    Ok(format!("Removed site: {}", id))
+                } else {
+                    Ok(format!("Site not found: {}", id))
+                }
+            }
+            Command::Pause(id) => set_enabled(config, &id, false),
+            Command::Resume(id) => set_enabled(config, &id, true),
+            Command::Check(id) => {
+                let Some(mut site) = find_site(config, &id) else {
+                    return Ok(format!("Site not found: {}", id));
+                };
+
+                // Persists the result itself; our copy is reloaded next command
+                match monitor.check_and_notify(&mut site).await? {
+                    Some(change) => Ok(format!(
+                        "Change detected on {}:\n\n{}",
+                        site.name, change.diff
+                    )),
+                    None if site.last_hash.is_some() => Ok(format!("No changes on {}.", site.name)),
+                    None => Ok(format!("Baseline saved for {}.", site.name)),
+                }
+            }
+            Command::Diff(id) => {
+                let Some(site) = find_site(config, &id) else {
+                    return Ok(format!("Site not found: {}", id));
+                };
+
+                self.last_diff(&site)
+            }
+            Command::Unmute(id) => {
+                let Some(mut site) = find_site(config, &id) else {
+                    return Ok(format!("Site not found: {}", id));
+                };
+
+                site.muted = false;
+                site.snoozed_until = None;
+                config.save_alert_state(&site)?;
+                Ok(format!("Change alerts resumed for {}", site.name))
+            }
+        }
+    }
+}
+
+fn find_site(config: &Config, id: &str) -> Option<WatchedSite> {
+    config
+        .app_config
+        .sites
+        .iter()
+        .find(|s| s.id == id || s.url == id)
+        .cloned()
+}
+
+/// Changes only `enabled`, so settings changed since `config` was loaded
+/// are kept.
+fn set_enabled(config: &mut Config, id: &str, enabled: bool) -> Result<String> {
+    let Some(site) = find_site(config, id) else {
+        return Ok(format!("Site not found: {}", id));
+    };
+
+    config.modify(|app_config| {
+        if let Some(existing) = app_config.sites.iter_mut().find(|s| s.id == site.id) {
+            existing.enabled = enabled;
+        }
+        Ok(())
+    })?;
+
+    let action = if enabled { "Resumed" } else { "Paused" };
+    Ok(format!("{} {}", action, site.name))
+}
+
+async fn reply(chat: &TelegramClient, text: &str) {
+    for part in TelegramMessage::text_parts(text) {
+        if let Err(e) = chat.send_message(&part).await {
+            error!("Failed to send Telegram reply: {}", e);
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_parse_with_their_arguments() {
+        assert_eq!(Command::parse("/list"), Some(Ok(Command::List)));
+        assert_eq!(Command::parse("/start"), Some(Ok(Command::Help)));
+        assert_eq!(
+            Command::parse("/pause@RnotBot a1b2"),
+            Some(Ok(Command::Pause("a1b2".to_string())))
+        );
+        assert_eq!(
+            Command::parse("/add https://example.com My  shop"),
+            Some(Ok(Command::Add {
+                url: "https://example.com".to_string(),
+                name: Some("My shop".to_string()),
+            }))
+        );
+        assert_eq!(
+            Command::parse("/add https://example.com"),
+            Some(Ok(Command::Add {
+                url: "https://example.com".to_string(),
+                name: None,
+            }))
+        );
+    }
+
+    #[test]
+    fn malformed_commands_get_a_hint_and_other_text_is_ignored() {
+        assert_eq!(
+            Command::parse("/diff"),
+            Some(Err("Usage: /diff <id>".to_string()))
+        );
+        assert!(matches!(
+            Command::parse("/frobnicate"),
+            Some(Err(hint)) if hint.starts_with("Unknown command /frobnicate")
+        ));
+        assert_eq!(Command::parse("hello /list"), None);
+        assert_eq!(Command::parse(""), None);
+    }
+}
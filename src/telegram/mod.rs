@@ -1,84 +1,397 @@
+mod bot;
+mod render;
+
+use crate::config::{LongDiffMode, WatchedSite};
+use crate::notify::{Notifier, PartialDigest, SiteChange};
 use anyhow::Result;
+use async_trait::async_trait;
 use reqwest::Client;
-use serde_json::json;
+use serde_json::{json, Value};
+use thiserror::Error;
+use tracing::warn;
+
+pub use bot::Bot;
+pub use render::{SiteAction, TelegramMessage};
+
+/// The most messages a diff is split over; longer ones are sent as a file.
+const MAX_SPLIT_PARTS: usize = 4;
+
+#[derive(Debug, Error)]
+pub enum TelegramError {
+    /// The API rejected the message markup ("can't parse entities").
+    #[error("Telegram could not parse message: {0}")]
+    Parse(String),
+    #[error("Telegram API error: {0}")]
+    Api(String),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+}
 
 pub struct TelegramClient {
     client: Client,
+    name: String,
     token: Option<String>,
     chat_id: Option<String>,
+    thread_id: Option<i64>,
 }
 
 impl TelegramClient {
     pub fn new(token: Option<String>, chat_id: Option<String>) -> Self {
         Self {
             client: Client::new(),
+            name: "telegram".to_string(),
             token,
             chat_id,
+            thread_id: None,
         }
     }
 
-    pub async fn send_message(&self, text: &str) -> Result<()> {
-        let token = match &self.token {
-            Some(t) => t,
-            None => return Ok(()), // Silently skip if no token
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Posts into a forum topic of the chat.
+    pub fn with_thread(mut self, thread_id: Option<i64>) -> Self {
+        self.thread_id = thread_id;
+        self
+    }
+
+    /// A client for another chat (and topic) under the same token.
+    pub fn for_chat(&self, chat_id: String, thread_id: Option<i64>) -> Self {
+        Self {
+            client: self.client.clone(),
+            name: self.name.clone(),
+            token: self.token.clone(),
+            chat_id: Some(chat_id),
+            thread_id,
+        }
+    }
+
+    /// Sends `message` as HTML, retrying as plain text if the markup is rejected.
+    pub async fn send_message(&self, message: &TelegramMessage) -> Result<()> {
+        match self
+            .send_text(&message.html, Some("HTML"), message.keyboard.as_ref())
+            .await
+        {
+            Err(TelegramError::Parse(description)) => {
+                warn!(
+                    "Telegram rejected HTML ({}), resending as plain text",
+                    description
+                );
+                Ok(self
+                    .send_text(&message.plain, None, message.keyboard.as_ref())
+                    .await?)
+            }
+            result => Ok(result?),
+        }
+    }
+
+    /// Uploads `content` as a file attachment named `file_name`.
+    pub async fn send_document(
+        &self,
+        file_name: &str,
+        content: Vec<u8>,
+        caption: &str,
+    ) -> Result<()> {
+        let (token, chat_id) = match self.destination() {
+            Some(destination) => destination,
+            None => return Ok(()),
         };
 
-        let chat_id = match &self.chat_id {
-            Some(id) => id.clone(),
-            None => return Ok(()), // Silently skip if no chat ID
+        let url = format!("https://api.telegram.org/bot{}/sendDocument", token);
+
+        let document = reqwest::multipart::Part::bytes(content)
+            .file_name(file_name.to_string())
+            .mime_str("text/x-diff")?;
+        let mut form = reqwest::multipart::Form::new()
+            .text("chat_id", chat_id.to_string())
+            .text("caption", caption.to_string())
+            .part("document", document);
+        if let Some(thread_id) = self.thread_id {
+            form = form.text("message_thread_id", thread_id.to_string());
+        }
+
+        let response = self.client.post(&url).multipart(form).send().await?;
+
+        if !response.status().is_success() {
+            let error = response.text().await?;
+            return Err(anyhow::anyhow!("Telegram API error: {}", error));
+        }
+
+        Ok(())
+    }
+
+    /// The token and chat ID, or `None` if either is missing and sends
+    /// should be skipped silently.
+    fn destination(&self) -> Option<(&str, &str)> {
+        Some((self.token.as_deref()?, self.chat_id.as_deref()?))
+    }
+
+    async fn send_text(
+        &self,
+        text: &str,
+        parse_mode: Option<&str>,
+        keyboard: Option<&Value>,
+    ) -> Result<(), TelegramError> {
+        let chat_id = match self.destination() {
+            Some((_, chat_id)) => chat_id,
+            None => return Ok(()),
         };
 
-        let url = format!("https://api.telegram.org/bot{}/sendMessage", token);
+        let mut body = json!({
+            "chat_id": chat_id,
+            "text": text,
+        });
+        if let Some(parse_mode) = parse_mode {
+            body["parse_mode"] = json!(parse_mode);
+        }
+        if let Some(thread_id) = self.thread_id {
+            body["message_thread_id"] = json!(thread_id);
+        }
+        if let Some(keyboard) = keyboard {
+            body["reply_markup"] = keyboard.clone();
+        }
+
+        self.call("sendMessage", &body).await.map(|_| ())
+    }
 
+    /// Acknowledges an inline button press, showing `text` as a toast.
+    pub async fn answer_callback_query(&self, callback_query_id: &str, text: &str) -> Result<()> {
         let body = json!({
-            "chat_id": chat_id,
+            "callback_query_id": callback_query_id,
             "text": text,
-            "parse_mode": "HTML",
         });
+        self.call("answerCallbackQuery", &body).await?;
+        Ok(())
+    }
 
-        let response = self.client.post(&url).json(&body).send().await?;
+    /// Removes the inline keyboard from a sent message.
+    pub async fn clear_keyboard(&self, chat_id: i64, message_id: i64) -> Result<()> {
+        let body = json!({
+            "chat_id": chat_id,
+            "message_id": message_id,
+            "reply_markup": { "inline_keyboard": [] },
+        });
+        self.call("editMessageReplyMarkup", &body).await?;
+        Ok(())
+    }
+
+    /// Calls a Bot API method, returning its `result`. Without a token this
+    /// does nothing and returns `null`.
+    async fn call(&self, method: &str, body: &Value) -> Result<Value, TelegramError> {
+        let token = match self.token {
+            Some(ref token) => token,
+            None => return Ok(Value::Null),
+        };
+
+        let url = format!("https://api.telegram.org/bot{}/{}", token, method);
+        let response = self.client.post(&url).json(body).send().await?;
 
         if !response.status().is_success() {
             let error = response.text().await?;
-            return Err(anyhow::anyhow!("Telegram API error: {}", error));
+            let description = serde_json::from_str::<Value>(&error)
+                .ok()
+                .and_then(|v| v["description"].as_str().map(String::from))
+                .unwrap_or(error);
+
+            if description.contains("can't parse entities") {
+                return Err(TelegramError::Parse(description));
+            }
+            return Err(TelegramError::Api(description));
         }
 
-        Ok(())
+        let json: Value = response.json().await?;
+        Ok(json["result"].clone())
     }
 
-    pub async fn get_updates(&self) -> Result<Vec<serde_json::Value>> {
+    /// Long-polls for updates after `offset`, waiting up to `timeout_secs`.
+    pub async fn poll_updates(&self, offset: Option<i64>, timeout_secs: u64) -> Result<Vec<Value>> {
         let token = self
             .token
             .as_ref()
             .ok_or_else(|| anyhow::anyhow!("No token configured"))?;
 
         let url = format!("https://api.telegram.org/bot{}/getUpdates", token);
-        let response = self.client.get(&url).send().await?;
+        let mut query = vec![("timeout", timeout_secs.to_string())];
+        if let Some(offset) = offset {
+            query.push(("offset", offset.to_string()));
+        }
+
+        let response = self
+            .client
+            .get(&url)
+            .query(&query)
+            .timeout(std::time::Duration::from_secs(timeout_secs + 10))
+            .send()
+            .await?;
 
         if !response.status().is_success() {
             let error = response.text().await?;
             return Err(anyhow::anyhow!("Telegram API error: {}", error));
         }
 
-        let json: serde_json::Value = response.json().await?;
-        let updates = json["result"].as_array().cloned().unwrap_or_default();
+        let json: Value = response.json().await?;
+        Ok(json["result"].as_array().cloned().unwrap_or_default())
+    }
+
+    /// Verifies the token, returning the bot's username.
+    pub async fn get_me(&self) -> Result<String> {
+        let me = self.call("getMe", &json!({})).await.map_err(|e| match e {
+            TelegramError::Api(description) => {
+                anyhow::anyhow!("Telegram rejected the token: {}", description)
+            }
+            e => e.into(),
+        })?;
 
-        Ok(updates)
+        me["username"]
+            .as_str()
+            .map(String::from)
+            .ok_or_else(|| anyhow::anyhow!("Unexpected getMe response: {}", me))
     }
+}
+
+/// A chat (or forum topic) the bot has heard from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatInfo {
+    pub chat_id: String,
+    pub thread_id: Option<i64>,
+    /// "private", "group", "supergroup" or "channel"
+    pub kind: String,
+    pub title: String,
+}
 
-    pub async fn get_chat_id_from_updates(&self) -> Result<Option<String>> {
-        let updates = self.get_updates().await?;
+impl ChatInfo {
+    /// The distinct chats in `updates`, most recently active first. Chats
+    /// the bot was added to count even before anyone posts in them.
+    pub fn from_updates(updates: &[Value]) -> Vec<Self> {
+        let mut chats: Vec<Self> = Vec::new();
 
         for update in updates.iter().rev() {
-            if let Some(message) = update.get("message") {
-                if let Some(chat) = message.get("chat") {
-                    if let Some(id) = chat.get("id") {
-                        return Ok(Some(id.to_string()));
+            let (message, thread_id) = match ["message", "channel_post", "my_chat_member"]
+                .iter()
+                .find_map(|key| update.get(*key))
+            {
+                Some(message) => {
+                    let thread_id = if message["is_topic_message"].as_bool() == Some(true) {
+                        message["message_thread_id"].as_i64()
+                    } else {
+                        None
+                    };
+                    (message, thread_id)
+                }
+                None => continue,
+            };
+
+            let chat = &message["chat"];
+            let Some(chat_id) = chat["id"].as_i64() else {
+                continue;
+            };
+
+            let title = match chat["title"].as_str() {
+                Some(title) => title.to_string(),
+                None => {
+                    let name = [chat["first_name"].as_str(), chat["last_name"].as_str()]
+                        .into_iter()
+                        .flatten()
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    match chat["username"].as_str() {
+                        Some(username) if name.is_empty() => format!("@{}", username),
+                        Some(username) => format!("{} (@{})", name, username),
+                        None => name,
                     }
                 }
+            };
+
+            let info = Self {
+                chat_id: chat_id.to_string(),
+                thread_id,
+                kind: chat["type"].as_str().unwrap_or("unknown").to_string(),
+                title,
+            };
+            if !chats
+                .iter()
+                .any(|c| c.chat_id == info.chat_id && c.thread_id == info.thread_id)
+            {
+                chats.push(info);
+            }
+        }
+
+        chats
+    }
+}
+
+#[async_trait]
+impl Notifier for TelegramClient {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn send_change(&self, change: &SiteChange) -> Result<()> {
+        let mut parts = TelegramMessage::change_parts(change);
+        let mode = match change.site.telegram_long_diff {
+            // A flood of messages is worse than a file
+            LongDiffMode::Split if parts.len() > MAX_SPLIT_PARTS => LongDiffMode::Document,
+            mode => mode,
+        };
+
+        match mode {
+            LongDiffMode::Truncate => {
+                let message = TelegramMessage::change(change).with_actions(&change.site);
+                self.send_message(&message).await
+            }
+            LongDiffMode::Split => {
+                // Buttons go under the last part, where reading ends
+                if let Some(last) = parts.pop() {
+                    parts.push(last.with_actions(&change.site));
+                }
+                for part in parts {
+                    self.send_message(&part).await?;
+                }
+                Ok(())
+            }
+            LongDiffMode::Document => {
+                let fits = parts.len() == 1;
+                let message = TelegramMessage::change(change).with_actions(&change.site);
+                self.send_message(&message).await?;
+
+                if !fits {
+                    let file_name = format!(
+                        "{}-{}.diff",
+                        change.site.id,
+                        change.detected_at.format("%Y%m%d-%H%M%S")
+                    );
+                    let caption = format!("Full diff for {}", change.site.name);
+                    self.send_document(
+                        &file_name,
+                        change.diff.to_full_string().into_bytes(),
+                        &caption,
+                    )
+                    .await?;
+                }
+                Ok(())
             }
         }
+    }
+
+    async fn send_digest(&self, changes: &[SiteChange]) -> Result<()> {
+        let mut sent = 0;
+        for (part, count) in TelegramMessage::digest_parts(changes) {
+            self.send_message(&part)
+                .await
+                .map_err(|error| PartialDigest { sent, error })?;
+            sent += count;
+        }
+        Ok(())
+    }
+
+    async fn send_error(&self, site: &WatchedSite, error: &str) -> Result<()> {
+        self.send_message(&TelegramMessage::error(site, error))
+            .await
+    }
 
-        Ok(None)
+    async fn send_recovery(&self, site: &WatchedSite) -> Result<()> {
+        self.send_message(&TelegramMessage::recovery(site)).await
     }
 }
@@ -0,0 +1,432 @@
+use crate::config::WatchedSite;
+use crate::notify::{escape_html, pack, truncate_chars, SiteChange, DIGEST_DIFF_CHARS};
+use chrono::Utc;
+use serde_json::{json, Value};
+
+/// Telegram rejects messages longer than this many characters.
+pub const MAX_MESSAGE_CHARS: usize = 4096;
+
+// Room left for the part counter and closing tags on continuation messages
+const PART_OVERHEAD: usize = 200;
+
+// How much of a site's URL a digest entry shows once escaped
+const DIGEST_URL_CHARS: usize = 1000;
+
+/// A message in Telegram HTML, with a plain-text twin to fall back on if
+/// the API rejects the markup.
+#[derive(Debug, Clone)]
+pub struct TelegramMessage {
+    pub html: String,
+    pub plain: String,
+    /// An inline keyboard (`reply_markup`) shown under the message.
+    pub keyboard: Option<Value>,
+}
+
+impl TelegramMessage {
+    /// A single message with as much of the diff as fits.
+    pub fn change(change: &SiteChange) -> Self {
+        let mut parts = Self::change_parts(change);
+        let truncated = parts.len() > 1;
+
+        let mut message = parts.swap_remove(0);
+        if truncated {
+            message.html.push_str("\n<i>... (truncated)</i>");
+            message.plain.push_str("\n... (truncated)");
+        }
+        message
+    }
+
+    /// The full diff, continued over as many messages as needed.
+    pub fn change_parts(change: &SiteChange) -> Vec<Self> {
+        let time = change.detected_at.format("%Y-%m-%d %H:%M:%S UTC");
+        let header_html = format!(
+            "🔄 <b>Change detected!</b>\n\n\
+            <b>Site:</b> {}\n\
+            <b>URL:</b> {}\n\
+            <b>Time:</b> {}\n\n\
+            <b>Changes:</b>\n",
+            escape_html(&change.site.name),
+            escape_html(&change.site.url),
+            time
+        );
+        let header_plain = format!(
+            "🔄 Change detected!\n\n\
+            Site: {}\n\
+            URL: {}\n\
+            Time: {}\n\n\
+            Changes:\n",
+            change.site.name, change.site.url, time
+        );
+
+        let diff = change.diff.to_full_string();
+        let first_budget = MAX_MESSAGE_CHARS
+            .saturating_sub(header_html.chars().count() + PART_OVERHEAD)
+            .max(PART_OVERHEAD);
+        let chunks = chunk_lines(&diff, first_budget, MAX_MESSAGE_CHARS - PART_OVERHEAD);
+        let total = chunks.len();
+
+        chunks
+            .iter()
+            .enumerate()
+            .map(|(i, lines)| {
+                let body = lines.join("\n");
+                let (html_prefix, plain_prefix) = if i == 0 {
+                    (header_html.clone(), header_plain.clone())
+                } else {
+                    (
+                        format!(
+                            "🔄 <b>{}</b> ({}/{})\n",
+                            escape_html(&change.site.name),
+                            i + 1,
+                            total
+                        ),
+                        format!("🔄 {} ({}/{})\n", change.site.name, i + 1, total),
+                    )
+                };
+
+                Self {
+                    html: format!("{}<pre>{}</pre>", html_prefix, escape_html(&body)),
+                    plain: format!("{}{}", plain_prefix, body),
+                    keyboard: None,
+                }
+            })
+            .collect()
+    }
+
+    /// Several changes summarised together, split into as many messages as
+    /// needed, each with the number of changes it covers. Each diff is cut
+    /// short, further if needed for its entry to fit in one message; the
+    /// full ones stay available via `/diff`.
+    pub fn digest_parts(changes: &[SiteChange]) -> Vec<(Self, usize)> {
+        let header_html = format!("📬 <b>{} change(s)</b>\n", changes.len());
+        let header_plain = format!("📬 {} change(s)\n", changes.len());
+        let budget = MAX_MESSAGE_CHARS - PART_OVERHEAD - header_html.chars().count();
+
+        let entries = changes
+            .iter()
+            .map(|change| {
+                let time = change.detected_at.format("%Y-%m-%d %H:%M UTC");
+                let url = fit_escaped(&change.site.url, DIGEST_URL_CHARS);
+                let entry_html = |diff: &str| {
+                    format!(
+                        "\n🔄 <b>{}</b> ({})\n{}\n<pre>{}</pre>\n",
+                        escape_html(&change.site.name),
+                        time,
+                        escape_html(&url),
+                        escape_html(diff)
+                    )
+                };
+                let room = budget.saturating_sub(entry_html("").chars().count());
+                let diff = truncate_chars(&change.diff.to_string(), DIGEST_DIFF_CHARS);
+                let diff = fit_escaped(&diff, room);
+                let plain = format!("\n🔄 {} ({})\n{}\n{}\n", change.site.name, time, url, diff);
+                (entry_html(&diff), plain)
+            })
+            .collect();
+
+        pack(entries, budget, |(html, _)| html.chars().count())
+            .into_iter()
+            .enumerate()
+            .map(|(i, run)| {
+                let count = run.len();
+                let (mut html, mut plain) = if i == 0 {
+                    (header_html.clone(), header_plain.clone())
+                } else {
+                    (String::new(), String::new())
+                };
+                for (entry_html, entry_plain) in run {
+                    html.push_str(&entry_html);
+                    plain.push_str(&entry_plain);
+                }
+                let message = Self {
+                    html,
+                    plain,
+                    keyboard: None,
+                };
+                (message, count)
+            })
+            .collect()
+    }
+
+    /// Attaches the alert actions for `site`; see [`SiteAction`].
+    pub fn with_actions(mut self, site: &WatchedSite) -> Self {
+        let button = |action: SiteAction| json!({ "text": action.label(), "callback_data": action.callback_data(&site.id) });
+
+        self.keyboard = Some(json!({
+            "inline_keyboard": [
+                [{ "text": "Open", "url": site.url }, button(SiteAction::Diff)],
+                [button(SiteAction::Snooze), button(SiteAction::Mute), button(SiteAction::Seen)],
+            ]
+        }));
+        self
+    }
+
+    /// Plain text, split into as many messages as needed.
+    pub fn text_parts(text: &str) -> Vec<Self> {
+        let budget = MAX_MESSAGE_CHARS - PART_OVERHEAD;
+        chunk_lines(text, budget, budget)
+            .into_iter()
+            .map(|lines| {
+                let plain = lines.join("\n");
+                Self {
+                    html: escape_html(&plain),
+                    plain,
+                    keyboard: None,
+                }
+            })
+            .collect()
+    }
+
+    pub fn error(site: &WatchedSite, error: &str) -> Self {
+        let time = Utc::now().format("%Y-%m-%d %H:%M:%S UTC");
+
+        Self {
+            html: format!(
+                "⚠️ <b>Check failing</b>\n\n\
+                <b>Site:</b> {}\n\
+                <b>URL:</b> {}\n\
+                <b>Time:</b> {}\n\n\
+                <b>Error:</b> {}",
+                escape_html(&site.name),
+                escape_html(&site.url),
+                time,
+                escape_html(error)
+            ),
+            plain: format!(
+                "⚠️ Check failing\n\nSite: {}\nURL: {}\nTime: {}\n\nError: {}",
+                site.name, site.url, time, error
+            ),
+            keyboard: None,
+        }
+    }
+
+    pub fn recovery(site: &WatchedSite) -> Self {
+        let time = Utc::now().format("%Y-%m-%d %H:%M:%S UTC");
+
+        Self {
+            html: format!(
+                "✅ <b>Check recovered</b>\n\n\
+                <b>Site:</b> {}\n\
+                <b>URL:</b> {}\n\
+                <b>Time:</b> {}",
+                escape_html(&site.name),
+                escape_html(&site.url),
+                time
+            ),
+            plain: format!(
+                "✅ Check recovered\n\nSite: {}\nURL: {}\nTime: {}",
+                site.name, site.url, time
+            ),
+            keyboard: None,
+        }
+    }
+}
+
+/// An inline button on a change alert, sent back as `<action>:<site id>`
+/// callback data.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SiteAction {
+    Diff,
+    Snooze,
+    Mute,
+    Seen,
+}
+
+impl SiteAction {
+    const ALL: [Self; 4] = [Self::Diff, Self::Snooze, Self::Mute, Self::Seen];
+
+    fn key(self) -> &'static str {
+        match self {
+            Self::Diff => "diff",
+            Self::Snooze => "snooze",
+            Self::Mute => "mute",
+            Self::Seen => "seen",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Diff => "Show full diff",
+            Self::Snooze => "Snooze 1d",
+            Self::Mute => "Mute site",
+            Self::Seen => "Mark as seen",
+        }
+    }
+
+    fn callback_data(self, site_id: &str) -> String {
+        format!("{}:{}", self.key(), site_id)
+    }
+
+    /// Splits callback data into the action and site ID.
+    pub fn parse(data: &str) -> Option<(Self, &str)> {
+        let (key, site_id) = data.split_once(':')?;
+        let action = Self::ALL.into_iter().find(|a| a.key() == key)?;
+        Some((action, site_id))
+    }
+}
+
+/// Groups diff lines into chunks whose escaped length fits the budget,
+/// hard-wrapping lines that are too long on their own.
+fn chunk_lines(diff: &str, first_budget: usize, budget: usize) -> Vec<Vec<String>> {
+    // Escaping can grow a character to six ("&quot;")
+    let max_piece = (first_budget.min(budget) / 6).max(1);
+
+    let mut chunks = Vec::new();
+    let mut current: Vec<String> = Vec::new();
+    let mut used = 0;
+    let mut limit = first_budget;
+
+    for line in diff.lines() {
+        let chars: Vec<char> = line.chars().collect();
+        let pieces: Vec<String> = if chars.is_empty() {
+            vec![String::new()]
+        } else {
+            chars
+                .chunks(max_piece)
+                .map(|piece| piece.iter().collect())
+                .collect()
+        };
+
+        for piece in pieces {
+            let len = escape_html(&piece).chars().count() + 1;
+            if used + len > limit && !current.is_empty() {
+                chunks.push(std::mem::take(&mut current));
+                used = 0;
+                limit = budget;
+            }
+            used += len;
+            current.push(piece);
+        }
+    }
+
+    if !current.is_empty() || chunks.is_empty() {
+        chunks.push(current);
+    }
+
+    chunks
+}
+
+/// Cuts `text` like [`truncate_chars`] so that it is at most `max`
+/// characters once escaped for HTML.
+fn fit_escaped(text: &str, max: usize) -> String {
+    if escape_html(text).chars().count() <= max {
+        return text.to_string();
+    }
+    let mut fitted = String::new();
+    let mut len = 0;
+    for c in text.chars() {
+        let c_len = escape_html(c.encode_utf8(&mut [0; 4])).chars().count();
+        if len + c_len + 1 > max {
+            break;
+        }
+        len += c_len;
+        fitted.push(c);
+    }
+    fitted.push('…');
+    fitted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::SiteDiff;
+
+    fn change(added: &str) -> SiteChange {
+        SiteChange {
+            site: WatchedSite::new(
+                "https://example.com".to_string(),
+                "Example".to_string(),
+                None,
+            ),
+            diff: SiteDiff::compute("", added),
+            old_content: String::new(),
+            new_content: added.to_string(),
+            detected_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn chunk_lines_fills_chunks_up_to_the_budget() {
+        // Each line costs its length plus a newline
+        let diff = ["aaaa"; 7].join("\n");
+        let sizes = |chunks: Vec<Vec<String>>| chunks.iter().map(Vec::len).collect::<Vec<_>>();
+        assert_eq!(sizes(chunk_lines(&diff, 30, 30)), [6, 1]);
+        // The first chunk may have less room, for the header
+        assert_eq!(sizes(chunk_lines(&diff, 24, 30)), [4, 3]);
+
+        assert_eq!(chunk_lines("", 30, 30), [Vec::<String>::new()]);
+    }
+
+    #[test]
+    fn chunk_lines_wraps_long_lines_and_counts_escapes() {
+        let chunks = chunk_lines(&"x".repeat(25), 60, 60);
+        let pieces: Vec<usize> = chunks.concat().iter().map(|p| p.len()).collect();
+        assert_eq!(pieces, [10, 10, 5]);
+
+        // "&" grows to "&amp;" once escaped
+        let chunks = chunk_lines("&&\n&&", 12, 12);
+        assert_eq!(chunks.len(), 2);
+    }
+
+    #[test]
+    fn change_parts_fit_in_messages() {
+        let lines: Vec<String> = (0..2000).map(|i| format!("line <{}>", i)).collect();
+        let parts = TelegramMessage::change_parts(&change(&lines.join("\n")));
+
+        assert!(parts.len() > 1);
+        for part in &parts {
+            assert!(part.html.chars().count() <= MAX_MESSAGE_CHARS);
+        }
+        assert!(parts[0].plain.starts_with("🔄 Change detected!"));
+        assert!(parts[1]
+            .plain
+            .starts_with(&format!("🔄 Example (2/{})", parts.len())));
+        // Every line arrives, in order
+        let plain: String = parts.iter().map(|part| part.plain.as_str()).collect();
+        assert!(plain.find("+ line <0>") < plain.find("+ line <1999>"));
+
+        let short = TelegramMessage::change(&change("one line"));
+        assert!(!short.plain.contains("truncated"));
+        assert!(TelegramMessage::change(&change(&lines.join("\n")))
+            .plain
+            .ends_with("... (truncated)"));
+    }
+
+    #[test]
+    fn digest_parts_fit_and_cover_every_change() {
+        let changes: Vec<SiteChange> = (0..40)
+            .map(|i| change(&format!("line {} {}", i, "x".repeat(400))))
+            .collect();
+        let parts = TelegramMessage::digest_parts(&changes);
+
+        assert!(parts.len() > 1);
+        assert_eq!(parts.iter().map(|(_, count)| count).sum::<usize>(), 40);
+        for (part, _) in &parts {
+            assert!(part.html.chars().count() <= MAX_MESSAGE_CHARS);
+        }
+        assert!(parts[0].0.plain.starts_with("📬 40 change(s)"));
+    }
+
+    #[test]
+    fn digest_entries_are_cut_to_fit_one_message() {
+        let mut changes = vec![change(&"<".repeat(5000))];
+        changes[0].site.url = format!("https://example.com/?q={}", "&".repeat(5000));
+        let parts = TelegramMessage::digest_parts(&changes);
+
+        assert_eq!(parts.len(), 1);
+        let (part, count) = &parts[0];
+        assert_eq!(*count, 1);
+        assert!(part.html.chars().count() <= MAX_MESSAGE_CHARS);
+        assert!(part.html.contains("&lt;&lt;…"));
+    }
+
+    #[test]
+    fn site_actions_round_trip_through_callback_data() {
+        for action in SiteAction::ALL {
+            let data = action.callback_data("a1b2");
+            assert_eq!(SiteAction::parse(&data), Some((action, "a1b2")));
+        }
+        assert_eq!(SiteAction::parse("seen"), None);
+        assert_eq!(SiteAction::parse("delete:a1b2"), None);
+    }
+}
@@ -0,0 +1,437 @@
+use crate::config::{self, WatchedSite};
+use crate::state::SiteState;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use quick_xml::events::{BytesDecl, BytesText, Event};
+use quick_xml::{Reader, Writer};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// File formats for `rnot export` and `rnot import`.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Format {
+    /// Outlines with `xmlUrl`, as feed readers use
+    Opml,
+    /// `{"sites": [...]}` with the same fields as config.toml
+    Json,
+    /// `url,name,selector` rows
+    Csv,
+    /// `[[sites]]` tables as in config.toml
+    Toml,
+}
+
+impl Format {
+    /// Guesses the format from a file extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "opml" | "xml" => Some(Self::Opml),
+            "json" => Some(Self::Json),
+            "csv" => Some(Self::Csv),
+            "toml" => Some(Self::Toml),
+            _ => None,
+        }
+    }
+
+    /// Only JSON and TOML carry every setting and the runtime state.
+    fn holds_state(self) -> bool {
+        matches!(self, Self::Json | Self::Toml)
+    }
+}
+
+/// A site as exported: its settings and, if requested, its runtime state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    #[serde(flatten)]
+    pub site: WatchedSite,
+    #[serde(flatten)]
+    pub state: SiteState,
+}
+
+#[derive(Serialize)]
+struct SiteList<'a> {
+    sites: &'a [Entry],
+}
+
+pub fn export(sites: &[WatchedSite], format: Format, with_state: bool) -> Result<String> {
+    if with_state && !format.holds_state() {
+        anyhow::bail!("Runtime state can only be exported as JSON or TOML");
+    }
+
+    let entries: Vec<Entry> = sites
+        .iter()
+        .map(|site| Entry {
+            site: site.clone(),
+            state: if with_state {
+                SiteState::of(site)
+            } else {
+                SiteState::default()
+            },
+        })
+        .collect();
+
+    match format {
+        Format::Json => Ok(serde_json::to_string_pretty(&SiteList { sites: &entries })? + "\n"),
+        Format::Toml => Ok(toml::to_string_pretty(&SiteList { sites: &entries })?),
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            writer.write_record(["url", "name", "selector"])?;
+            for site in sites {
+                writer.write_record([
+                    site.url.as_str(),
+                    site.name.as_str(),
+                    site.css_selector.as_deref().unwrap_or(""),
+                ])?;
+            }
+            let csv = writer
+                .into_inner()
+                .map_err(|e| anyhow::anyhow!("Failed to write CSV: {}", e.error()))?;
+            Ok(String::from_utf8(csv)?)
+        }
+        Format::Opml => write_opml(sites),
+    }
+}
+
+fn write_opml(sites: &[WatchedSite]) -> Result<String> {
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+
+    writer
+        .create_element("opml")
+        .with_attribute(("version", "2.0"))
+        .write_inner_content(|writer| {
+            writer
+                .create_element("head")
+                .write_inner_content(|writer| {
+                    writer
+                        .create_element("title")
+                        .write_text_content(BytesText::new("RNOT sites"))?;
+                    Ok(())
+                })?;
+            writer
+                .create_element("body")
+                .write_inner_content(|writer| {
+                    for site in sites {
+                        writer
+                            .create_element("outline")
+                            .with_attributes([
+                                ("text", site.name.as_str()),
+                                ("title", site.name.as_str()),
+                                ("type", "rss"),
+                                ("xmlUrl", site.url.as_str()),
+                            ])
+                            .write_empty()?;
+                    }
+                    Ok(())
+                })?;
+            Ok(())
+        })?;
+
+    Ok(String::from_utf8(writer.into_inner())? + "\n")
+}
+
+/// Reads the sites in `content`. Every site gets the ID [`config::generate_id`]
+/// gives its URL, so the same URL is recognized whatever the file says;
+/// sites without a name are named after their host.
+pub fn parse(content: &str, format: Format) -> Result<Vec<Entry>> {
+    match format {
+        Format::Json => parse_structured(serde_json::from_str(content)?),
+        Format::Toml => parse_structured(toml::from_str(content)?),
+        Format::Csv => parse_csv(content),
+        Format::Opml => parse_opml(content),
+    }
+}
+
+/// JSON or TOML, either a list of sites or an object with a `sites` list.
+fn parse_structured(value: serde_json::Value) -> Result<Vec<Entry>> {
+    let sites = match value {
+        serde_json::Value::Array(sites) => sites,
+        serde_json::Value::Object(mut object) => match object.remove("sites") {
+            Some(serde_json::Value::Array(sites)) => sites,
+            _ => anyhow::bail!("Expected a `sites` list"),
+        },
+        _ => anyhow::bail!("Expected a list of sites"),
+    };
+
+    sites
+        .into_iter()
+        .enumerate()
+        .map(|(i, site)| {
+            let serde_json::Value::Object(mut site) = site else {
+                anyhow::bail!("Site {} is not a table", i + 1);
+            };
+            let url = site
+                .get("url")
+                .and_then(|url| url.as_str())
+                .with_context(|| format!("Site {} has no url", i + 1))?
+                .to_string();
+
+            site.insert("id".to_string(), config::generate_id(&url).into());
+            site.entry("name").or_insert_with(|| host_name(&url).into());
+            site.entry("enabled").or_insert(true.into());
+
+            serde_json::from_value(serde_json::Value::Object(site))
+                .with_context(|| format!("Site {} ({})", i + 1, url))
+        })
+        .collect()
+}
+
+fn parse_csv(content: &str) -> Result<Vec<Entry>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(content.as_bytes());
+
+    let mut entries = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        let record = record?;
+        let field = |n| record.get(n).filter(|f: &&str| !f.is_empty());
+
+        let Some(url) = field(0) else {
+            continue;
+        };
+        // An optional header row
+        if i == 0 && url.eq_ignore_ascii_case("url") {
+            continue;
+        }
+
+        let name = field(1).map_or_else(|| host_name(url), String::from);
+        entries.push(Entry {
+            site: WatchedSite::new(url.to_string(), name, field(2).map(String::from)),
+            state: SiteState::default(),
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Takes every outline with a URL, however deeply nested in folders.
+fn parse_opml(content: &str) -> Result<Vec<Entry>> {
+    let mut reader = Reader::from_str(content);
+    let mut entries = Vec::new();
+
+    loop {
+        let outline = match reader.read_event()? {
+            Event::Eof => break,
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"outline" => e,
+            _ => continue,
+        };
+
+        let (mut text, mut title, mut url, mut html_url) = (None, None, None, None);
+        for attribute in outline.attributes() {
+            let attribute = attribute?;
+            let value = attribute.unescape_value()?.trim().to_string();
+            if value.is_empty() {
+                continue;
+            }
+            match attribute.key.local_name().as_ref() {
+                b"text" => text = Some(value),
+                b"title" => title = Some(value),
+                b"xmlUrl" | b"url" => url = Some(value),
+                b"htmlUrl" => html_url = Some(value),
+                _ => {}
+            }
+        }
+
+        let Some(url) = url.or(html_url) else {
+            continue;
+        };
+        let name = text.or(title).unwrap_or_else(|| host_name(&url));
+        entries.push(Entry {
+            site: WatchedSite::new(url, name, None),
+            state: SiteState::default(),
+        });
+    }
+
+    Ok(entries)
+}
+
+fn host_name(url: &str) -> String {
+    url::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(|s| s.to_string()))
+        .unwrap_or_else(|| url.to_string())
+}
+
+/// What importing a list of sites would do.
+#[derive(Debug, Default)]
+pub struct Plan {
+    pub added: Vec<Entry>,
+    /// Already watched, or earlier in the same file.
+    pub duplicates: Vec<Entry>,
+    /// Sites `rnot add` would refuse or config.toml could not load, with
+    /// the reason.
+    pub invalid: Vec<(Entry, String)>,
+}
+
+pub fn plan(existing: &[WatchedSite], entries: Vec<Entry>) -> Plan {
+    let mut seen: HashSet<String> = existing
+        .iter()
+        .flat_map(|site| [site.id.clone(), config::generate_id(&site.url)])
+        .collect();
+    let mut plan = Plan::default();
+
+    for entry in entries {
+        let site = &entry.site;
+        let checked = config::validate_site(&site.url, &site.name, site.css_selector.as_deref())
+            .and_then(|()| {
+                site.tags
+                    .iter()
+                    .try_for_each(|tag| config::validate_tag(tag))
+            })
+            .and_then(|()| config::compile_rules("ignore", &site.ignore).map(drop));
+
+        if let Err(e) = checked {
+            plan.invalid.push((entry, e.to_string()));
+        } else if seen.insert(site.id.clone()) {
+            plan.added.push(entry);
+        } else {
+            plan.duplicates.push(entry);
+        }
+    }
+
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn site(url: &str, name: &str) -> WatchedSite {
+        WatchedSite::new(url.to_string(), name.to_string(), None)
+    }
+
+    fn urls(entries: &[Entry]) -> Vec<&str> {
+        entries.iter().map(|e| e.site.url.as_str()).collect()
+    }
+
+    #[test]
+    fn csv_rows_name_sites_after_their_host_by_default() {
+        let csv = "url,name,selector\n\
+                   https://example.com/a, Shop ,#price\n\
+                   https://example.org/b\n\
+                   ,no url\n";
+        let entries = parse(csv, Format::Csv).unwrap();
+
+        assert_eq!(
+            urls(&entries),
+            ["https://example.com/a", "https://example.org/b"]
+        );
+        assert_eq!(entries[0].site.name, "Shop");
+        assert_eq!(entries[0].site.css_selector.as_deref(), Some("#price"));
+        assert_eq!(entries[1].site.name, "example.org");
+        assert_eq!(entries[1].site.css_selector, None);
+    }
+
+    #[test]
+    fn opml_outlines_are_read_from_every_folder() {
+        let opml = r#"<?xml version="1.0"?>
+            <opml version="2.0"><body>
+              <outline text="News">
+                <outline text="Blog" xmlUrl="https://example.com/feed"/>
+                <outline title="Page" htmlUrl="https://example.org/"/>
+              </outline>
+              <outline text="Folder without URL"/>
+            </body></opml>"#;
+        let entries = parse(opml, Format::Opml).unwrap();
+
+        assert_eq!(
+            urls(&entries),
+            ["https://example.com/feed", "https://example.org/"]
+        );
+        assert_eq!(entries[0].site.name, "Blog");
+        assert_eq!(entries[1].site.name, "Page");
+    }
+
+    #[test]
+    fn structured_files_get_ids_from_their_urls() {
+        let json =
+            r#"{"sites": [{"id": "made-up", "url": "https://example.com", "tags": ["shops"]}]}"#;
+        let entries = parse(json, Format::Json).unwrap();
+
+        assert_eq!(
+            entries[0].site.id,
+            config::generate_id("https://example.com")
+        );
+        assert_eq!(entries[0].site.name, "example.com");
+        assert!(entries[0].site.enabled);
+        assert_eq!(entries[0].site.tags, ["shops"]);
+
+        let list = r#"[{"url": "https://example.com", "enabled": false}]"#;
+        assert!(!parse(list, Format::Json).unwrap()[0].site.enabled);
+
+        let error = parse(r#"[{"name": "No URL"}]"#, Format::Json).unwrap_err();
+        assert_eq!(error.to_string(), "Site 1 has no url");
+    }
+
+    #[test]
+    fn exported_state_reads_back() {
+        let mut shop = site("https://example.com", "Shop");
+        shop.last_hash = Some("abc".to_string());
+        shop.tags = vec!["shops".to_string()];
+
+        for format in [Format::Json, Format::Toml] {
+            let exported = export(std::slice::from_ref(&shop), format, true).unwrap();
+            let entries = parse(&exported, format).unwrap();
+            assert_eq!(entries[0].site.name, "Shop");
+            assert_eq!(entries[0].site.tags, ["shops"]);
+            assert_eq!(entries[0].state.last_hash.as_deref(), Some("abc"));
+        }
+        assert!(export(&[shop], Format::Csv, true).is_err());
+    }
+
+    #[test]
+    fn plan_sorts_entries_into_added_duplicate_and_invalid() {
+        let existing = [site("https://example.com/a", "A")];
+        let mut entries = parse(
+            "https://example.com/a\n\
+             https://example.com/b\n\
+             https://example.com/b\n\
+             ftp://example.com/c\n",
+            Format::Csv,
+        )
+        .unwrap();
+        entries.extend(
+            parse(
+                r#"[{"url": "https://example.com/d", "ignore": ["("]},
+                    {"url": "https://example.com/e", "tags": ["no spaces"]}]"#,
+                Format::Json,
+            )
+            .unwrap(),
+        );
+
+        let plan = plan(&existing, entries);
+
+        assert_eq!(urls(&plan.added), ["https://example.com/b"]);
+        assert_eq!(
+            urls(&plan.duplicates),
+            ["https://example.com/a", "https://example.com/b"]
+        );
+        let invalid: Vec<(&str, &str)> = plan
+            .invalid
+            .iter()
+            .map(|(entry, reason)| (entry.site.url.as_str(), reason.as_str()))
+            .collect();
+        assert_eq!(invalid.len(), 3);
+        assert_eq!(
+            invalid[0],
+            (
+                "ftp://example.com/c",
+                "Only HTTP and HTTPS URLs are supported"
+            )
+        );
+        assert_eq!(invalid[1].0, "https://example.com/d");
+        assert!(
+            invalid[1].1.starts_with("ignore: invalid regex \"(\""),
+            "{}",
+            invalid[1].1
+        );
+        assert_eq!(invalid[2].0, "https://example.com/e");
+        assert!(
+            invalid[2].1.starts_with("Invalid tag \"no spaces\""),
+            "{}",
+            invalid[2].1
+        );
+    }
+}
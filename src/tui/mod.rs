@@ -1,6 +1,7 @@
-use crate::config::WatchedSite;
+use crate::config::{Config, WatchedSite};
 use crate::monitor::MonitorEvent;
-use crate::Config;
+use crate::storage::{self, CheckRecord, Storage};
+use chrono::Utc;
 use crossterm::{
     event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
     execute,
@@ -10,13 +11,22 @@ use ratatui::{
     backend::CrosstermBackend,
     layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
+    symbols,
     text::{Line, Span},
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    widgets::{
+        Axis, Block, Borders, Chart, Dataset, GraphType, List, ListItem, ListState, Paragraph,
+        Sparkline,
+    },
     Frame, Terminal,
 };
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
 use std::io;
 use tokio::sync::mpsc::Receiver;
 
+/// Checks charted per site; about ten days at the default interval.
+const CHART_CHECKS: usize = 5000;
+
 pub struct App {
     pub sites: Vec<WatchedSite>,
     pub list_state: ListState,
@@ -27,6 +37,9 @@ pub struct App {
     pub status_message: String,
     pub has_token: bool,
     pub pending_url: Option<String>,
+    pub chart_mode: ChartMode,
+    /// The latest checks of each site charted so far, by site ID.
+    history: HashMap<String, Vec<CheckRecord>>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -39,8 +52,36 @@ pub enum InputMode {
     Help,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChartMode {
+    Changes,
+    ResponseTime,
+    Value,
+}
+
+impl ChartMode {
+    pub fn next(self) -> Self {
+        match self {
+            ChartMode::Changes => ChartMode::ResponseTime,
+            ChartMode::ResponseTime => ChartMode::Value,
+            ChartMode::Value => ChartMode::Changes,
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            ChartMode::Changes => "Changes per hour",
+            ChartMode::ResponseTime => "Response time (ms)",
+            ChartMode::Value => "Tracked value",
+        }
+    }
+}
+
 impl App {
-    pub fn new(sites: Vec<WatchedSite>, has_token: bool, _has_chat_id: bool) -> Self {
+    pub fn new(mut sites: Vec<WatchedSite>, has_token: bool, _has_chat_id: bool) -> Self {
+        // Grouped by first tag, untagged sites last; config order within
+        sites.sort_by(|a, b| group_of(a).cmp(&group_of(b)));
+
         Self {
             sites,
             list_state: ListState::default(),
@@ -51,9 +92,37 @@ impl App {
             status_message: String::new(),
             has_token,
             pending_url: None,
+            chart_mode: ChartMode::Changes,
+            history: HashMap::new(),
         }
     }
 
+    pub fn selected_site(&self) -> Option<&WatchedSite> {
+        self.list_state.selected().and_then(|i| self.sites.get(i))
+    }
+
+    /// Loads the latest checks of the selected site unless they are
+    /// already loaded.
+    pub fn load_history(&mut self, storage: &dyn Storage) {
+        let Some(site_id) = self.selected_site().map(|s| s.id.clone()) else {
+            return;
+        };
+
+        if let Entry::Vacant(entry) = self.history.entry(site_id) {
+            let history = storage
+                .recent_history(entry.key(), CHART_CHECKS)
+                .unwrap_or_default();
+            entry.insert(history);
+        }
+    }
+
+    /// The loaded checks of the selected site, oldest first.
+    pub fn history(&self) -> &[CheckRecord] {
+        self.selected_site()
+            .and_then(|site| self.history.get(&site.id))
+            .map_or(&[], Vec::as_slice)
+    }
+
     pub fn add_log(&mut self, message: String) {
         let timestamp = chrono::Local::now().format("%H:%M:%S");
         self.logs.push(format!("[{}] {}", timestamp, message));
@@ -91,6 +160,13 @@ impl App {
     }
 }
 
+/// The group a site is listed under: its first tag, `None` if untagged.
+/// `None` sorts last.
+fn group_of(site: &WatchedSite) -> (bool, Option<&str>) {
+    let tag = site.tags.first().map(String::as_str);
+    (tag.is_none(), tag)
+}
+
 pub fn run_tui(config: &mut Config, mut events_rx: Receiver<MonitorEvent>) -> anyhow::Result<()> {
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -124,10 +200,14 @@ fn run_app(
     events_rx: &mut Receiver<MonitorEvent>,
     config: &mut Config,
 ) -> anyhow::Result<()> {
+    let storage = storage::open(config)?;
+
     loop {
         if let Ok(event) = events_rx.try_recv() {
             match event {
                 MonitorEvent::SiteChecked { site_id, changed } => {
+                    // Reloaded with the new check once selected
+                    app.history.remove(&site_id);
                     if let Some(site) = app.sites.iter().find(|s| s.id == site_id) {
                         app.add_log(format!(
                             "Checked: {} ({})",
@@ -137,6 +217,7 @@ fn run_app(
                     }
                 }
                 MonitorEvent::SiteChanged { site_id, diff } => {
+                    app.history.remove(&site_id);
                     if let Some(site) = app.sites.iter().find(|s| s.id == site_id) {
                         app.add_log(format!(
                             "CHANGED: {} - {}",
@@ -146,11 +227,28 @@ fn run_app(
                     }
                 }
                 MonitorEvent::Error { site_id, error } => {
+                    app.history.remove(&site_id);
                     app.add_log(format!("Error on {}: {}", site_id, error));
                 }
+                MonitorEvent::HookFinished {
+                    site_id,
+                    hook,
+                    exit_code,
+                    output,
+                } => {
+                    let status = match exit_code {
+                        Some(code) => format!("exit {}", code),
+                        None => "failed".to_string(),
+                    };
+                    app.add_log(format!(
+                        "Hook {} on {}: {} {}",
+                        hook, site_id, status, output
+                    ));
+                }
             }
         }
 
+        app.load_history(storage.as_ref());
         terminal.draw(|f| ui(f, app))?;
 
         if event::poll(std::time::Duration::from_millis(100))? {
@@ -193,6 +291,9 @@ fn run_app(
                         KeyCode::Char('?') => {
                             app.input_mode = InputMode::Help;
                         }
+                        KeyCode::Char('g') => {
+                            app.chart_mode = app.chart_mode.next();
+                        }
                         KeyCode::Char('r') => {
                             if let Some(i) = app.list_state.selected() {
                                 if i < app.sites.len() {
@@ -203,6 +304,7 @@ fn run_app(
                         _ => {}
                     },
                     InputMode::AddUrl => match key.code {
+                        #[allow(clippy::collapsible_match)]
                         KeyCode::Enter => {
                             if !app.input.is_empty() {
                                 app.pending_url = Some(app.input.clone());
@@ -271,17 +373,8 @@ fn run_app(
                             };
 
                             match config.add_site(url.clone(), name.clone(), selector) {
-                                Ok(id) => {
-                                    let site = WatchedSite {
-                                        id,
-                                        url,
-                                        name,
-                                        last_hash: None,
-                                        last_checked: None,
-                                        last_change: None,
-                                        enabled: true,
-                                        css_selector: None,
-                                    };
+                                Ok(_) => {
+                                    let site = WatchedSite::new(url, name, None);
                                     app.add_log(format!("Added: {}", site.name));
                                     app.sites.push(site);
                                 }
@@ -358,20 +451,26 @@ fn ui(f: &mut Frame, app: &App) {
 
     let chunks = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .constraints([
+            Constraint::Percentage(40),
+            Constraint::Percentage(25),
+            Constraint::Percentage(35),
+        ])
         .split(f.area());
 
+    let grouped = app.sites.iter().any(|site| !site.tags.is_empty());
     let sites: Vec<ListItem> = app
         .sites
         .iter()
-        .map(|site| {
+        .enumerate()
+        .map(|(i, site)| {
             let status = if site.enabled { "✓" } else { "✗" };
             let checked = site
                 .last_checked
                 .map(|t| t.format("%H:%M:%S").to_string())
                 .unwrap_or_else(|| "Never".to_string());
 
-            ListItem::new(Line::from(vec![
+            let mut spans = vec![
                 Span::styled(status.to_string() + " ", Style::default().fg(Color::Green)),
                 Span::styled(
                     site.name.clone(),
@@ -379,7 +478,29 @@ fn ui(f: &mut Frame, app: &App) {
                 ),
                 Span::raw(" - "),
                 Span::styled(checked, Style::default().fg(Color::DarkGray)),
-            ]))
+            ];
+            // Further tags; the first one is the heading the site is under
+            for tag in site.tags.iter().skip(1) {
+                spans.push(Span::styled(
+                    format!(" #{}", tag),
+                    Style::default().fg(Color::DarkGray),
+                ));
+            }
+
+            // The heading is part of the group's first item, so list
+            // indices stay those of `app.sites`
+            let mut lines = Vec::new();
+            let group = group_of(site).1;
+            if grouped && (i == 0 || group_of(&app.sites[i - 1]).1 != group) {
+                lines.push(Line::from(Span::styled(
+                    format!("── {} ──", group.unwrap_or("untagged")),
+                    Style::default()
+                        .fg(Color::Cyan)
+                        .add_modifier(Modifier::BOLD),
+                )));
+            }
+            lines.push(Line::from(spans));
+            ListItem::new(lines)
         })
         .collect();
 
@@ -396,6 +517,8 @@ fn ui(f: &mut Frame, app: &App) {
     let mut sites_state = app.list_state.clone();
     f.render_stateful_widget(sites_list, chunks[0], &mut sites_state);
 
+    render_chart(f, app, chunks[1]);
+
     let logs: Vec<ListItem> = app
         .logs
         .iter()
@@ -407,7 +530,7 @@ fn ui(f: &mut Frame, app: &App) {
     let logs_list =
         List::new(logs).block(Block::default().title("Activity Log").borders(Borders::ALL));
 
-    f.render_widget(logs_list, chunks[1]);
+    f.render_widget(logs_list, chunks[2]);
 
     if !app.status_message.is_empty() || !app.input.is_empty() {
         let input_area = Rect::new(chunks[0].x, chunks[0].bottom() + 1, chunks[0].width, 3);
@@ -424,6 +547,113 @@ fn ui(f: &mut Frame, app: &App) {
     }
 }
 
+fn render_chart(f: &mut Frame, app: &App, area: Rect) {
+    let title = match app.selected_site() {
+        Some(site) => format!("{} - {} (g: switch)", app.chart_mode.title(), site.name),
+        None => format!("{} (g: switch)", app.chart_mode.title()),
+    };
+    let block = Block::default().title(title).borders(Borders::ALL);
+    let width = area.width.saturating_sub(2) as usize;
+
+    match app.chart_mode {
+        ChartMode::Changes => {
+            let data = change_frequency(app.history(), width);
+            let sparkline = Sparkline::default()
+                .block(block)
+                .data(&data)
+                .style(Style::default().fg(Color::Yellow));
+            f.render_widget(sparkline, area);
+        }
+        ChartMode::ResponseTime => {
+            // Failed checks have no response time to show
+            let times: Vec<u64> = app
+                .history()
+                .iter()
+                .filter(|r| r.error.is_none())
+                .map(|r| r.response_ms)
+                .collect();
+            let data = times[times.len().saturating_sub(width)..].to_vec();
+            let sparkline = Sparkline::default()
+                .block(block)
+                .data(&data)
+                .style(Style::default().fg(Color::Cyan));
+            f.render_widget(sparkline, area);
+        }
+        ChartMode::Value => {
+            let points: Vec<(f64, f64)> = app
+                .history()
+                .iter()
+                .filter_map(|r| r.value.map(|v| (r.timestamp.timestamp() as f64, v)))
+                .collect();
+
+            if points.is_empty() {
+                let empty =
+                    Paragraph::new("No tracked values (set track_value = true for this site)")
+                        .style(Style::default().fg(Color::DarkGray))
+                        .block(block);
+                f.render_widget(empty, area);
+                return;
+            }
+
+            let (x_min, x_max) = bounds(points.iter().map(|p| p.0));
+            let (y_min, y_max) = bounds(points.iter().map(|p| p.1));
+            let time_label = |secs: f64| {
+                chrono::DateTime::from_timestamp(secs as i64, 0)
+                    .map(|t| t.format("%m-%d %H:%M").to_string())
+                    .unwrap_or_default()
+            };
+
+            let dataset = Dataset::default()
+                .marker(symbols::Marker::Braille)
+                .graph_type(GraphType::Line)
+                .style(Style::default().fg(Color::Green))
+                .data(&points);
+
+            let chart = Chart::new(vec![dataset])
+                .block(block)
+                .x_axis(
+                    Axis::default()
+                        .style(Style::default().fg(Color::DarkGray))
+                        .bounds([x_min, x_max])
+                        .labels(vec![time_label(x_min), time_label(x_max)]),
+                )
+                .y_axis(
+                    Axis::default()
+                        .style(Style::default().fg(Color::DarkGray))
+                        .bounds([y_min, y_max])
+                        .labels(vec![format!("{:.2}", y_min), format!("{:.2}", y_max)]),
+                );
+            f.render_widget(chart, area);
+        }
+    }
+}
+
+/// Counts changes per hour for the last `buckets` hours, oldest first.
+fn change_frequency(history: &[CheckRecord], buckets: usize) -> Vec<u64> {
+    let mut data = vec![0; buckets];
+    let now = Utc::now();
+
+    for record in history.iter().filter(|r| r.changed) {
+        let hours_ago = (now - record.timestamp).num_hours();
+        if hours_ago >= 0 && (hours_ago as usize) < buckets {
+            data[buckets - 1 - hours_ago as usize] += 1;
+        }
+    }
+
+    data
+}
+
+fn bounds(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    let (min, max) = values.fold((f64::MAX, f64::MIN), |(min, max), v| {
+        (min.min(v), max.max(v))
+    });
+    if min == max {
+        (min - 1.0, max + 1.0)
+    } else {
+        (min, max)
+    }
+}
+
 fn render_help(f: &mut Frame) {
     let help_text = vec![
         "RNOT - Website Monitor Help",
@@ -433,6 +663,7 @@ fn render_help(f: &mut Frame) {
         "  d     - Delete selected site",
         "  t     - Set Telegram bot token",
         "  r     - Refresh selected site",
+        "  g     - Switch chart (changes/response time/value)",
         "  j/↓   - Move down in list",
         "  k/↑   - Move up in list",
         "  q     - Quit",
@@ -440,8 +671,9 @@ fn render_help(f: &mut Frame) {
         "",
         "CLI Commands:",
         "  rnot add <URL> [--name NAME] [--selector CSS]",
-        "  rnot remove <ID|URL>",
-        "  rnot list",
+        "  rnot remove <ID|URL> | --tag TAG",
+        "  rnot list [--tag TAG]",
+        "  rnot pause|resume <ID|URL> | --tag TAG",
         "  rnot set-token <TOKEN>",
         "  rnot telegram-setup",
         "  rnot daemon",